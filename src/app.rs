@@ -1,80 +1,802 @@
-use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
-use ratatui::DefaultTerminal;
-use std::{env::home_dir, time::Duration};
+use color_eyre::eyre::eyre;
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent,
+    MouseEventKind,
+};
+use ratatui::{layout::Position, DefaultTerminal};
+use signal_hook::{
+    consts::{SIGCONT, SIGTSTP},
+    iterator::Signals,
+};
+use std::{
+    env::home_dir,
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
     browser::{self, Browser},
+    command::{Adjust, Command, CommandLine},
+    config::Config,
+    cue,
+    devices::Devices,
+    equalizer::Equalizer,
+    format,
+    keymap::{Context, Keymap},
+    library::{self, Library},
+    notifications::{Notifications, TOAST_DURATION},
+    now_playing::NowPlaying,
+    overview::Overview,
     player_controller::PlayerController,
+    playlist::{self, PlaylistFormat},
+    playlists::Playlists,
+    scanner::ScanJob,
+    session::Session,
+    song::Song,
+    terminal,
+    theme::Theme,
     ui,
+    visualizer::{self, Visualizer},
 };
 
+/// Longest time between the two clicks of a double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// How often the position and progress are redrawn while something plays.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Longest wait for an event, background work like a scan is checked at
+/// least this often.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the main loop waits for.
+pub enum AppEvent {
+    /// Terminal input, read on its own thread.
+    Input(io::Result<Event>),
+    Signal(i32),
+    /// The player sent the controller a message.
+    Player,
+}
+
 pub struct App {
     running: bool,
     ui: ui::Ui,
+    config: Config,
     pub browser: browser::Browser,
+    pub playlists: Playlists,
+    pub library: Library,
     pub player_controller: PlayerController,
+    pub scan: Option<ScanJob>,
+    pub equalizer: Equalizer,
+    pub devices: Devices,
+    pub visualizer: Visualizer,
+    pub overview: Overview,
+    pub now_playing: NowPlaying,
+    pub keymap: Keymap,
+    pub command_line: CommandLine,
+    pub theme: Theme,
+    pub notifications: Notifications,
+    /// Input, signals and player messages, all on one channel so any of
+    /// them wakes up the main loop.
+    events: mpsc::Receiver<AppEvent>,
+    event_sender: mpsc::Sender<AppEvent>,
+    /// Time and place of the last click, to recognise double clicks.
+    last_click: Option<(Instant, Position)>,
+    /// Set by Ctrl-Z, the terminal is handed back before the next frame.
+    suspend: bool,
+    /// Whether the screen is out of date.
+    redraw: bool,
+    /// Whether the last frame showed a notification, it has to be cleared
+    /// once it expires.
+    toast_shown: bool,
 }
 
 impl App {
     pub fn new() -> color_eyre::Result<App> {
+        let music_dir = home_dir().unwrap_or_default().join("Music");
+
+        let config = Config::load()?;
+        let equalizer = Equalizer::new(&config);
+        let visualizer = Visualizer::new();
+        let keymap = Keymap::new(&config.keys)?;
+        let theme = Theme::load(&config.appearance.theme)?;
+        let (event_sender, events) = mpsc::channel();
+
         Ok(App {
             running: true,
             ui: ui::Ui::new(),
             browser: Browser::new(),
-            player_controller: PlayerController::new()?,
+            playlists: Playlists::new()?,
+            library: Library::new(music_dir)?,
+            player_controller: PlayerController::new(
+                config.playback.clone(),
+                config.output.clone(),
+                equalizer.get_handle(),
+                visualizer.get_tap(),
+                event_sender.clone(),
+            )?,
+            scan: None,
+            equalizer,
+            devices: Devices::new(),
+            visualizer,
+            overview: Overview::new(),
+            now_playing: NowPlaying::new(),
+            keymap,
+            command_line: CommandLine::new(),
+            theme,
+            notifications: Notifications::new(),
+            events,
+            event_sender,
+            last_click: None,
+            suspend: false,
+            redraw: true,
+            toast_shown: false,
+            config,
         })
     }
 
+    /// Runs until the user quits or a signal like SIGTERM arrives. The
+    /// queue is saved and the player shut down however the loop ends, even
+    /// by a panic.
     pub fn run(&mut self, mut terminal: DefaultTerminal) -> color_eyre::Result<()> {
-        let mut music_dir = home_dir().unwrap();
-        music_dir.push("Music");
-
-        if music_dir.exists() {
+        if home_dir().is_some_and(|home| home.join("Music").exists()) {
             self.browser.go_to("Music")?;
         }
 
+        let session = Session::load().and_then(|session| {
+            self.player_controller
+                .restore(session.get_songs(), session.get_position())
+        });
+
+        if let Err(e) = session {
+            self.notifications.error(e);
+        }
+
+        let mut signals = Signals::new(terminal::SIGNALS)?;
+        let sender = self.event_sender.clone();
+
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                if sender.send(AppEvent::Signal(signal)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let sender = self.event_sender.clone();
+
+        thread::spawn(move || loop {
+            let event = event::read();
+            let failed = event.is_err();
+
+            if sender.send(AppEvent::Input(event)).is_err() || failed {
+                break;
+            }
+        });
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.run_loop(&mut terminal)));
+
+        let position = self.player_controller.get_position();
+        let saved = self
+            .player_controller
+            .queue
+            .get_queue()
+            .and_then(|songs| Session::new(&songs, position).save());
+
+        self.player_controller.shutdown();
+
+        match result {
+            Ok(result) => result.and(saved),
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+
+    fn run_loop(&mut self, terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
+        // Errors from here on are shown as notifications, only a broken
+        // terminal ends the program.
         while self.running {
-            self.player_controller.check_for_message()?;
-            terminal.draw(|frame| self.ui.draw(self, frame).expect("UI Error"))?;
-            self.handle_events()?;
+            if self.suspend {
+                self.suspend = false;
+                terminal::suspend(terminal)?;
+                self.redraw = true;
+            }
+
+            match self.player_controller.check_for_message() {
+                Ok(received) => self.redraw |= received,
+                Err(e) => self.report(e),
+            }
+
+            for error in self.player_controller.take_errors() {
+                self.report(error);
+            }
+
+            for panic in terminal::take_panics() {
+                self.report(panic);
+            }
+
+            match self.update_library() {
+                Ok(changed) => self.redraw |= changed,
+                Err(e) => self.report(e),
+            }
+
+            if self.redraw {
+                self.redraw = false;
+
+                if self.ui.get_current_window() == ui::Window::Visualizer {
+                    self.visualizer.update();
+                }
+
+                let mut drawn = Ok(());
+                terminal.draw(|frame| drawn = self.ui.draw(self, frame))?;
+
+                // Not reported with a redraw, a frame that keeps failing
+                // would be drawn over and over.
+                if let Err(e) = drawn {
+                    self.notifications.error(e);
+                }
+
+                self.toast_shown = self.notifications.get_toast().is_some();
+            }
+
+            self.handle_events(terminal)?;
         }
 
         Ok(())
     }
 
-    fn handle_events(&mut self) -> color_eyre::Result<()> {
-        if event::poll(Duration::from_millis(50))? {
-            match event::read()? {
-                Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                    self.handle_key_event(key_event)?
-                }
-                _ => {}
+    /// Shows an error and draws the frame again to show it.
+    fn report(&mut self, error: impl ToString) {
+        self.notifications.error(error);
+        self.redraw = true;
+    }
+
+    /// Takes in the results of the background work, returns whether
+    /// anything on screen may have changed.
+    fn update_library(&mut self) -> color_eyre::Result<bool> {
+        let mut changed = self.library.check_for_update()?;
+
+        for path in self.player_controller.take_played() {
+            self.library.record_play(&path)?;
+            changed = true;
+        }
+
+        self.playlists.update(&self.library)?;
+
+        if let Some(scan) = &mut self.scan {
+            changed |= scan.update();
+
+            if let Some(error) = scan.take_error() {
+                self.notifications.error(format!("Scan failed. {error}"));
+            }
+        }
+
+        let current = self.player_controller.queue.get_current_song()?;
+
+        self.overview.load(current.as_ref());
+        changed |= self.overview.update();
+
+        // Probing the file and reading its tags may be slow, so it is only
+        // done while the window is shown.
+        if self.ui.get_current_window() == ui::Window::NowPlaying {
+            self.now_playing.load(current.as_ref());
+        }
+
+        changed |= self.now_playing.update();
+
+        // Each device has its own equalizer profile.
+        if let Some(device) = self.player_controller.get_device()
+            && device != self.equalizer.get_device()
+        {
+            self.equalizer.set_device(device.to_string(), &self.config);
+        }
+
+        Ok(changed)
+    }
+
+    /// How often a new frame is needed while nothing happens, None if
+    /// nothing on screen moves.
+    fn get_tick(&self) -> Option<Duration> {
+        let playing = self.player_controller.is_playing();
+
+        match self.ui.get_current_window() {
+            ui::Window::Visualizer if playing => Some(visualizer::FRAME_INTERVAL),
+            _ if playing
+                || self.now_playing.is_loading()
+                || self.scan.as_ref().is_some_and(|scan| !scan.is_finished()) =>
+            {
+                Some(TICK)
+            }
+            // The age of each message.
+            ui::Window::Log => Some(IDLE_INTERVAL),
+            _ => None,
+        }
+    }
+
+    /// Waits for an event until the next tick. Without a tick the wait is
+    /// long, only background work is checked in between.
+    fn handle_events(&mut self, terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
+        let tick = self.get_tick();
+        let toast = self
+            .notifications
+            .get_toast()
+            .map(|toast| TOAST_DURATION.saturating_sub(toast.time.elapsed()));
+
+        let timeout = [tick, toast]
+            .into_iter()
+            .flatten()
+            .fold(IDLE_INTERVAL, Duration::min);
+
+        let event = match self.events.recv_timeout(timeout) {
+            Ok(AppEvent::Input(event)) => event?,
+            Ok(AppEvent::Signal(signal)) => return self.handle_signal(terminal, signal),
+            // Its messages are handled at the top of the loop.
+            Ok(AppEvent::Player) => return Ok(()),
+            Err(_) => {
+                self.redraw |=
+                    tick.is_some() || self.toast_shown && self.notifications.get_toast().is_none();
+
+                return Ok(());
+            }
+        };
+
+        // Any other input, including a resize, may change the screen.
+        if !matches!(
+            event,
+            Event::Mouse(MouseEvent {
+                kind: MouseEventKind::Moved,
+                ..
+            })
+        ) {
+            self.redraw = true;
+        }
+
+        let result = match event {
+            Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
+                self.handle_key_event(key_event)
             }
+            Event::Mouse(mouse_event) => self.handle_mouse_event(mouse_event),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            self.notifications.error(e);
+        }
+
+        Ok(())
+    }
+
+    fn handle_signal(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        signal: i32,
+    ) -> color_eyre::Result<()> {
+        match signal {
+            SIGTSTP => self.suspend = true,
+            SIGCONT => {
+                terminal::resume(terminal)?;
+                self.redraw = true;
+            }
+            // SIGINT, SIGTERM and SIGHUP
+            _ => self.exit(),
         }
 
         Ok(())
     }
 
     fn handle_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        // Raw mode turns these into keys instead of signals.
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+            match key_event.code {
+                KeyCode::Char('c') => {
+                    self.exit();
+                    return Ok(());
+                }
+                KeyCode::Char('z') => {
+                    self.suspend = true;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        // Text input grabs every key, including the global bindings.
+        if self.ui.get_current_window() == ui::Window::Playlists && self.playlists.is_editing() {
+            return self.playlists.handle_key_event(
+                key_event,
+                &mut self.player_controller,
+                &self.browser,
+            );
+        }
+
+        if self.command_line.is_active() {
+            let dir = self.browser.get_current_path();
+
+            if let Some(line) = self.command_line.handle_key_event(key_event, &dir) {
+                self.run_command(&line);
+            }
+
+            return Ok(());
+        }
+
+        if self.ui.is_help_shown() {
+            return self.handle_help_key_event(key_event);
+        }
+
+        self.command_line.set_status(None);
+
+        // Remapped keys are turned back into the defaults the windows know.
+        let Some((context, code)) = self.keymap.resolve(self.get_key_context(), key_event.code)
+        else {
+            return Ok(());
+        };
+
+        let key_event = KeyEvent::new(code, key_event.modifiers);
+
+        if context != Context::Global {
+            return self.handle_window_key_event(key_event);
+        }
+
         match key_event.code {
             KeyCode::Char('q') => self.exit(),
             KeyCode::Char('1') => self.ui.set_current_window(ui::Window::Home),
             KeyCode::Char('2') => self.ui.set_current_window(ui::Window::Browser),
             KeyCode::Char('3') => self.ui.set_current_window(ui::Window::Queue),
+            KeyCode::Char('4') => self.ui.set_current_window(ui::Window::Playlists),
+            KeyCode::Char('5') => self.ui.set_current_window(ui::Window::Equalizer),
+            KeyCode::Char('6') => self.ui.set_current_window(ui::Window::Devices),
+            KeyCode::Char('7') => self.ui.set_current_window(ui::Window::Visualizer),
+            KeyCode::Char('8') => self.ui.set_current_window(ui::Window::NowPlaying),
+            KeyCode::Char('9') => self.ui.set_current_window(ui::Window::Log),
             KeyCode::Char('p') => self.player_controller.toggle()?,
             KeyCode::Char('s') => self.player_controller.skip()?,
             KeyCode::Char('S') => self.player_controller.stop()?,
-            _ => {
-                if self.ui.get_current_window() == ui::Window::Browser {
-                    self.browser
-                        .handle_key_event(key_event, &mut self.player_controller)?
+            KeyCode::Char('+') => self.player_controller.speed_up()?,
+            KeyCode::Char('-') => self.player_controller.speed_down()?,
+            KeyCode::Char('0') => self.player_controller.reset_speed()?,
+            KeyCode::Char(':') => self.command_line.open(),
+            KeyCode::Char('?') => self.ui.toggle_help(),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Runs a line from the command line. Errors are shown in the status
+    /// line rather than ending the program.
+    fn run_command(&mut self, line: &str) {
+        if line.is_empty() {
+            return;
+        }
+
+        match Command::parse(line).and_then(|command| self.execute_command(command)) {
+            Ok(status) => self.command_line.set_status(status),
+            Err(e) => self.command_line.set_error(e.to_string()),
+        }
+    }
+
+    /// Returns a message for the status line, if there is anything to say.
+    fn execute_command(&mut self, command: Command) -> color_eyre::Result<Option<String>> {
+        let dir = self.browser.get_current_path();
+        let pc = &mut self.player_controller;
+
+        Ok(match command {
+            Command::Add(path) => Some(self.add_path(&dir.join(path))?),
+            Command::Cd(path) => {
+                // Resolves `..` and `.`, the browser expects a plain path.
+                let path = dir.join(path);
+                let path = fs::canonicalize(&path)
+                    .map_err(|e| eyre!("Can't open {}. {e}", path.display()))?;
+
+                if !path.is_dir() && !cue::is_cue(&path) {
+                    return Err(eyre!("{} isn't a directory.", path.display()));
                 }
+
+                self.browser.set_current_path(path);
+                self.ui.set_current_window(ui::Window::Browser);
+                None
+            }
+            Command::Export { path, overwrite } => {
+                let path = dir.join(path);
+
+                if PlaylistFormat::from_path(&path).is_none() {
+                    return Err(eyre!("Use a .m3u8, .pls or .xspf file."));
+                }
+
+                if path.exists() && !overwrite {
+                    return Err(eyre!(
+                        "{} already exists, use export! to replace it.",
+                        path.display()
+                    ));
+                }
+
+                playlist::write(&path, &pc.queue.get_queue()?)?;
+                self.notifications
+                    .info(format!("Exported the queue to {}.", path.display()));
+                None
+            }
+            Command::Quit => {
+                self.exit();
+                None
+            }
+            Command::Save(name) => {
+                self.playlists.save(&name, &pc.queue.get_queue()?)?;
+                Some(format!("Saved the queue as \"{name}\"."))
+            }
+            Command::Seek(adjust) => {
+                if pc.queue.get_current_song()?.is_none() {
+                    return Err(eyre!("Nothing is playing."));
+                }
+
+                let position = pc.get_position();
+                let target = match adjust {
+                    Adjust::To(target) => target,
+                    Adjust::Up(offset) => position
+                        .checked_add(offset)
+                        .ok_or_else(|| eyre!("Can't seek that far."))?,
+                    Adjust::Down(offset) => position.saturating_sub(offset),
+                };
+
+                pc.seek(pc.get_length().map_or(target, |length| target.min(length)))?;
+                None
+            }
+            Command::Shuffle(shuffle) => {
+                let shuffle = shuffle.unwrap_or(!pc.is_shuffled());
+                pc.set_shuffle(shuffle)?;
+                Some(format!("Shuffle {}.", if shuffle { "on" } else { "off" }))
             }
+            Command::Theme(name) => {
+                self.theme = Theme::load(&name)?;
+                self.config.appearance.theme = name;
+                self.config.save()?;
+                Some(format!("Switched to the {} theme.", self.theme.get_name()))
+            }
+            Command::Volume(adjust) => {
+                let volume = pc.get_volume();
+
+                pc.set_volume(match adjust {
+                    Adjust::To(volume) => volume,
+                    Adjust::Up(step) => volume.saturating_add(step),
+                    Adjust::Down(step) => volume.saturating_sub(step),
+                })?;
+
+                Some(format!("Volume {}%.", pc.get_volume()))
+            }
+        })
+    }
+
+    /// Queues a song, every song below a directory, the entries of a
+    /// playlist or the tracks of a CUE sheet.
+    fn add_path(&mut self, path: &Path) -> color_eyre::Result<String> {
+        let songs: Vec<Song> = if path.is_dir() {
+            let mut files = vec![];
+            library::collect_songs(path, &mut files);
+
+            let mut paths: Vec<PathBuf> = files.into_iter().map(|(path, _)| path).collect();
+            paths.sort();
+
+            paths.into_iter().map(Song::new).collect()
+        } else if cue::is_cue(path) {
+            cue::read(path)?
+                .into_iter()
+                .map(|track| track.into_song())
+                .collect()
+        } else if playlist::is_playlist(path) {
+            playlist::read(path)?
+                .into_iter()
+                .filter(|entry| entry.get_path().is_file())
+                .map(|entry| entry.into_song())
+                .collect()
+        } else if format::is_supported(path) {
+            vec![Song::new(path.to_path_buf())]
+        } else if path.exists() {
+            return Err(eyre!("Can't play {}.", path.display()));
+        } else {
+            return Err(eyre!("{} doesn't exist.", path.display()));
+        };
+
+        let count = songs.len();
+
+        self.player_controller.play_songs(songs)?;
+
+        Ok(match count {
+            1 => String::from("Added 1 song."),
+            count => format!("Added {count} songs."),
+        })
+    }
+
+    /// The help takes every key while it is open.
+    fn handle_help_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        let help = self.keymap.get_key(Context::Global, "help");
+
+        match key_event.code {
+            KeyCode::Esc => self.ui.toggle_help(),
+            code if Some(code) == help => self.ui.toggle_help(),
+            KeyCode::Char('j') | KeyCode::Down => self.ui.scroll_help(1),
+            KeyCode::Char('k') | KeyCode::Up => self.ui.scroll_help(-1),
+            _ => {}
         }
 
         Ok(())
     }
 
+    /// Bindings that apply to the current window.
+    fn get_key_context(&self) -> Context {
+        match self.ui.get_current_window() {
+            ui::Window::Home | ui::Window::NowPlaying => Context::Global,
+            ui::Window::Browser => Context::Browser,
+            ui::Window::Queue => Context::Queue,
+            ui::Window::Playlists if self.playlists.get_detail().is_some() => Context::Playlist,
+            ui::Window::Playlists => Context::Playlists,
+            ui::Window::Equalizer => Context::Equalizer,
+            ui::Window::Devices => Context::Devices,
+            ui::Window::Visualizer => Context::Visualizer,
+            ui::Window::Log => Context::Log,
+        }
+    }
+
+    fn handle_window_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        match self.ui.get_current_window() {
+            ui::Window::Browser => self.handle_browser_key_event(key_event)?,
+            ui::Window::Queue => self.handle_queue_key_event(key_event)?,
+            ui::Window::Playlists => self.playlists.handle_key_event(
+                key_event,
+                &mut self.player_controller,
+                &self.browser,
+            )?,
+            ui::Window::Equalizer => self
+                .equalizer
+                .handle_key_event(key_event, &mut self.config)?,
+            ui::Window::Devices => self.devices.handle_key_event(
+                key_event,
+                &mut self.config,
+                &mut self.player_controller,
+            )?,
+            ui::Window::Visualizer => self.visualizer.handle_key_event(key_event),
+            ui::Window::Log => self.notifications.handle_key_event(key_event),
+            ui::Window::Home | ui::Window::NowPlaying => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_mouse_event(&mut self, mouse_event: MouseEvent) -> color_eyre::Result<()> {
+        // Text input keeps the focus until it is closed.
+        if self.command_line.is_active()
+            || self.ui.get_current_window() == ui::Window::Playlists && self.playlists.is_editing()
+        {
+            return Ok(());
+        }
+
+        if self.ui.is_help_shown() {
+            match mouse_event.kind {
+                MouseEventKind::ScrollDown => self.ui.scroll_help(1),
+                MouseEventKind::ScrollUp => self.ui.scroll_help(-1),
+                _ => {}
+            }
+
+            return Ok(());
+        }
+
+        let position = Position::new(mouse_event.column, mouse_event.row);
+
+        match mouse_event.kind {
+            MouseEventKind::Down(MouseButton::Left) => self.handle_click(position)?,
+            // The wheel moves through lists like j and k.
+            MouseEventKind::ScrollDown if self.ui.has_list() => {
+                self.handle_window_key_event(KeyEvent::from(KeyCode::Char('j')))?
+            }
+            MouseEventKind::ScrollUp if self.ui.has_list() => {
+                self.handle_window_key_event(KeyEvent::from(KeyCode::Char('k')))?
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Tabs switch windows, the overview seeks, list entries are selected
+    /// and opened like with Enter on a double click.
+    fn handle_click(&mut self, position: Position) -> color_eyre::Result<()> {
+        let double_click = self
+            .last_click
+            .is_some_and(|(time, last)| last == position && time.elapsed() <= DOUBLE_CLICK);
+
+        // A third click starts over instead of making another double click.
+        self.last_click = match double_click {
+            true => None,
+            false => Some((Instant::now(), position)),
+        };
+
+        if let Some(window) = self.ui.get_tab_at(position) {
+            self.ui.set_current_window(window);
+            return Ok(());
+        }
+
+        let area = self.ui.get_overview_area();
+
+        if area.contains(position) {
+            if let Some(length) = self.player_controller.get_length() {
+                let fraction = (position.x - area.x) as f32 / area.width as f32;
+                self.player_controller.seek(length.mul_f32(fraction))?;
+            }
+
+            return Ok(());
+        }
+
+        if let Some(index) = self.ui.get_list_index_at(position) {
+            match self.ui.get_current_window() {
+                ui::Window::Browser => self.browser.set_selected(index)?,
+                ui::Window::Queue => self.player_controller.queue.set_selected(index),
+                ui::Window::Playlists => self.playlists.set_selected(index)?,
+                ui::Window::Devices => self.devices.set_selected(index),
+                ui::Window::Log => self.notifications.set_selected(index),
+                _ => return Ok(()),
+            }
+
+            if double_click {
+                self.handle_window_key_event(KeyEvent::from(KeyCode::Enter))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_browser_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        match key_event.code {
+            KeyCode::Char('g') => self.start_scan(false)?,
+            KeyCode::Char('G') => self.start_scan(true)?,
+            _ => self
+                .browser
+                .handle_key_event(key_event, &mut self.player_controller)?,
+        }
+
+        Ok(())
+    }
+
+    /// Measures the loudness of the selected file or directory in the
+    /// background, `write` also stores the result in the files' tags.
+    fn start_scan(&mut self, write: bool) -> color_eyre::Result<()> {
+        if self.scan.as_ref().is_some_and(|scan| !scan.is_finished()) {
+            return Ok(());
+        }
+
+        self.scan = Some(ScanJob::start(
+            self.browser.get_selected_full_path()?,
+            write,
+        ));
+
+        Ok(())
+    }
+
+    fn handle_queue_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        let queue = &mut self.player_controller.queue;
+        let selected = queue.get_selected();
+
+        match key_event.code {
+            KeyCode::Char('h') => queue.set_selected(0),
+            KeyCode::Char('j') => queue.set_selected(selected + 1),
+            KeyCode::Char('k') => queue.set_selected(selected.saturating_sub(1)),
+            KeyCode::Char('l') => queue.set_selected(usize::MAX),
+            KeyCode::Enter => self.player_controller.skip_to(selected)?,
+            KeyCode::Char('w') => self.export_queue(PlaylistFormat::M3u),
+            KeyCode::Char('P') => self.export_queue(PlaylistFormat::Pls),
+            KeyCode::Char('X') => self.export_queue(PlaylistFormat::Xspf),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Asks for the file name on the command line, with a default to edit.
+    fn export_queue(&mut self, format: PlaylistFormat) {
+        self.command_line
+            .open_with(&format!("export queue.{}", format.extension()));
+    }
+
     fn exit(&mut self) {
         self.running = false;
     }
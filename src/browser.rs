@@ -1,15 +1,29 @@
-use audiotags::Tag;
 use color_eyre::eyre::eyre;
 use crossterm::event::{KeyCode, KeyEvent};
-use std::{env::home_dir, fs::read_dir, path::PathBuf};
-
-use crate::{
-    player::PlayerCommand, player_controller::PlayerController, player_message::PlayerMessage,
+use std::{
+    cell::RefCell,
+    env::home_dir,
+    fs::{self, read_dir},
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+    time::SystemTime,
 };
 
+use crate::{cue, format, player_controller::PlayerController, playlist, song::Song};
+
 pub struct Browser {
     current_path: PathBuf,
     selected: u32,
+    /// The last listing, read again once the directory changes.
+    listing: RefCell<Option<Listing>>,
+}
+
+/// Entries of a directory or CUE sheet and its modification time when
+/// they were read.
+struct Listing {
+    path: PathBuf,
+    modified: SystemTime,
+    entries: Vec<PathBuf>,
 }
 
 impl Browser {
@@ -25,6 +39,7 @@ impl Browser {
         Browser {
             current_path: home,
             selected: 0,
+            listing: RefCell::new(None),
         }
     }
 
@@ -39,6 +54,7 @@ impl Browser {
             KeyCode::Char('k') => self.select_previous()?,
             KeyCode::Char('l') => self.select_last()?,
             KeyCode::Enter => self.select(pc)?,
+            KeyCode::Char('r') => self.replace_queue(pc)?,
             KeyCode::Backspace => self.go_back()?,
             _ => {}
         }
@@ -46,29 +62,70 @@ impl Browser {
         Ok(())
     }
 
-    pub fn list_dir(&self) -> color_eyre::Result<Vec<String>> {
+    /// Entries of the current directory. Reading them means reading the
+    /// tags of every song for its track number, so the listing is kept
+    /// until files are added, removed or renamed.
+    /// Names aren't required to be UTF-8, they are only shown lossily.
+    pub fn list_dir(&self) -> color_eyre::Result<Vec<PathBuf>> {
+        let modified = fs::metadata(&self.current_path)?.modified().ok();
+
+        if let Some(listing) = self.listing.borrow().as_ref()
+            && listing.path == self.current_path
+            && Some(listing.modified) == modified
+        {
+            return Ok(listing.entries.clone());
+        }
+
+        let entries = self.read_dir()?;
+
+        *self.listing.borrow_mut() = modified.map(|modified| Listing {
+            path: self.current_path.clone(),
+            modified,
+            entries: entries.clone(),
+        });
+
+        Ok(entries)
+    }
+
+    fn read_dir(&self) -> color_eyre::Result<Vec<PathBuf>> {
+        if cue::is_cue(&self.current_path) {
+            return Ok(cue::read(&self.current_path)?
+                .iter()
+                .map(|track| self.current_path.join(track.get_display_name()))
+                .collect());
+        }
+
         let mut data = read_dir(self.current_path.clone())?
-            .filter_map(|item| {
-                item.ok()
-                    .map(|entry| {
-                        entry
-                            .path()
-                            .into_os_string()
-                            .into_string()
-                            .expect("String conversion")
-                    })
-                    .filter(|entry| !entry.contains("/."))
-            })
-            .collect::<Vec<String>>();
+            .filter_map(|item| item.ok())
+            .filter(|entry| !entry.file_name().as_bytes().starts_with(b"."))
+            .map(|entry| entry.path())
+            .collect::<Vec<PathBuf>>();
 
         data.sort();
 
-        if data
+        // Audio files split by a CUE sheet are listed inside the sheet.
+        let referenced: Vec<PathBuf> = data
             .iter()
-            .any(|entry| entry.ends_with(".mp3") || entry.ends_with(".flac"))
-        {
+            .filter(|entry| cue::is_cue(entry))
+            .filter_map(|entry| cue::read(entry).ok())
+            .flatten()
+            .map(|track| track.get_file())
+            .collect();
+
+        data.retain(|entry| !referenced.contains(entry));
+
+        if data.iter().any(|entry| format::is_supported(entry)) {
             match self.sort_songs(&data) {
-                Ok(sorted_songs) => return Ok(sorted_songs),
+                Ok(mut sorted_songs) => {
+                    // Keep playlists, CUE sheets and subdirectories reachable.
+                    sorted_songs.extend(
+                        data.iter()
+                            .filter(|entry| !format::is_supported(entry))
+                            .cloned(),
+                    );
+
+                    return Ok(sorted_songs);
+                }
                 Err(_e) => return Ok(data),
             };
         }
@@ -76,15 +133,13 @@ impl Browser {
         Ok(data)
     }
 
-    fn get_track_numbers(&self, data: &[String]) -> color_eyre::Result<Vec<u16>> {
+    fn get_track_numbers(&self, data: &[PathBuf]) -> color_eyre::Result<Vec<u16>> {
         let track_numbers: color_eyre::Result<Vec<u16>> = data
             .iter()
-            .filter(|entry| entry.ends_with(".mp3") || entry.ends_with(".flac"))
+            .filter(|entry| format::is_supported(entry))
             .map(|entry| {
-                Tag::new()
-                    .read_from_path(entry)
-                    .map_err(|e| eyre!("Failed to read tag. {e}"))?
-                    .track_number()
+                format::read_tags(entry)?
+                    .track_number
                     .ok_or_else(|| eyre!("File does not contain track number."))
             })
             .collect();
@@ -92,17 +147,17 @@ impl Browser {
         track_numbers
     }
 
-    fn sort_songs(&self, data: &[String]) -> color_eyre::Result<Vec<String>> {
-        let songs: Vec<String> = data
+    fn sort_songs(&self, data: &[PathBuf]) -> color_eyre::Result<Vec<PathBuf>> {
+        let songs: Vec<PathBuf> = data
             .iter()
-            .filter(|entry| entry.ends_with(".mp3") || entry.ends_with(".flac"))
-            .filter_map(|entry| entry.split("/").last())
-            .map(|entry| entry.to_string())
+            .filter(|entry| format::is_supported(entry))
+            .filter_map(|entry| entry.file_name())
+            .map(PathBuf::from)
             .collect();
 
         let track_numbers = self.get_track_numbers(data)?;
 
-        let mut vec_tupel: Vec<(String, u16)> = vec![];
+        let mut vec_tupel: Vec<(PathBuf, u16)> = vec![];
 
         for i in 0..track_numbers.len() {
             vec_tupel.push((songs[i].clone(), track_numbers[i]));
@@ -113,7 +168,7 @@ impl Browser {
         Ok(vec_tupel
             .into_iter()
             .map(|entry| entry.0)
-            .collect::<Vec<String>>())
+            .collect::<Vec<PathBuf>>())
     }
 
     pub fn go_to(&mut self, path: &str) -> color_eyre::Result<()> {
@@ -138,7 +193,7 @@ impl Browser {
     }
 
     fn select_next(&mut self) -> color_eyre::Result<()> {
-        if self.selected < self.get_selected_len()? - 1 {
+        if self.selected + 1 < self.get_selected_len()? {
             self.selected += 1;
         }
 
@@ -154,36 +209,75 @@ impl Browser {
     }
 
     fn select_last(&mut self) -> color_eyre::Result<()> {
-        self.selected = self.get_selected_len()? - 1;
+        self.selected = self.get_selected_len()?.saturating_sub(1);
         Ok(())
     }
 
     pub fn select(&mut self, player_controller: &mut PlayerController) -> color_eyre::Result<()> {
-        let mut path = self.current_path.clone();
-        path.push(self.get_selected_path()?);
-
-        if path.is_dir() {
+        let path = self.get_selected_full_path()?;
+
+        if let Some((cue_path, number)) = cue::parse_virtual_entry(&path) {
+            // The rest of the sheet follows, like the rest of an album.
+            player_controller.play_songs(
+                cue::read(&cue_path)?
+                    .into_iter()
+                    .skip_while(|track| track.get_number() != number)
+                    .map(|track| track.into_song()),
+            )?;
+        } else if path.is_dir() || cue::is_cue(&path) {
             self.select_first()?;
             self.current_path = path;
-        } else if path
-            .as_path()
-            .extension()
-            .is_some_and(|ext| ext == "mp3" || ext == "flac")
-        {
-            player_controller.send_command(PlayerMessage::new(PlayerCommand::Play, Some(path)))?;
+        } else if format::is_supported(&path) {
+            player_controller.play_song(Song::new(path))?;
+        } else if playlist::is_playlist(&path) {
+            player_controller.load_playlist(playlist::read(&path)?, false)?;
         }
 
         Ok(())
     }
 
+    fn replace_queue(
+        &mut self,
+        player_controller: &mut PlayerController,
+    ) -> color_eyre::Result<()> {
+        let path = self.get_selected_full_path()?;
+
+        if playlist::is_playlist(&path) {
+            player_controller.load_playlist(playlist::read(&path)?, true)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_current_path(&self) -> PathBuf {
+        self.current_path.clone()
+    }
+
+    pub fn set_current_path(&mut self, path: PathBuf) {
+        self.current_path = path;
+        self.selected = 0;
+    }
+
     pub fn get_selected(&self) -> color_eyre::Result<u32> {
         Ok(self.selected)
     }
 
+    pub fn set_selected(&mut self, selected: usize) -> color_eyre::Result<()> {
+        self.selected = (selected as u32).min(self.get_selected_len()?.saturating_sub(1));
+        Ok(())
+    }
+
     fn get_selected_path(&self) -> color_eyre::Result<PathBuf> {
-        Ok(PathBuf::from(
-            self.list_dir()?[self.get_selected()? as usize].clone(),
-        ))
+        self.list_dir()?
+            .get(self.get_selected()? as usize)
+            .cloned()
+            .ok_or_else(|| eyre!("{} is empty.", self.current_path.display()))
+    }
+
+    pub fn get_selected_full_path(&self) -> color_eyre::Result<PathBuf> {
+        let mut path = self.current_path.clone();
+        path.push(self.get_selected_path()?);
+        Ok(path)
     }
 
     fn get_selected_len(&self) -> color_eyre::Result<u32> {
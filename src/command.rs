@@ -0,0 +1,401 @@
+use color_eyre::eyre::eyre;
+use crossterm::event::{KeyCode, KeyEvent};
+use std::{
+    env::home_dir,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{paths, theme::Theme};
+
+/// Commands offered by the completion, with their arguments.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("add", "<path>"),
+    ("cd", "[path]"),
+    ("export", "<file.m3u8|.pls|.xspf>"),
+    ("quit", ""),
+    ("save", "<name>"),
+    ("seek", "[+|-]<[h:]m:ss | seconds>"),
+    ("shuffle", "[on|off|toggle]"),
+    ("theme", "<name>"),
+    ("vol", "[+|-]<0-100>"),
+];
+
+/// Commands kept in the history file.
+const HISTORY_LEN: usize = 100;
+
+/// A value to set, or to change the current one by.
+pub enum Adjust<T> {
+    To(T),
+    Up(T),
+    Down(T),
+}
+
+pub enum Command {
+    /// Queues a song, a directory, a playlist or a CUE sheet.
+    Add(PathBuf),
+    /// Opens a directory in the browser.
+    Cd(PathBuf),
+    /// Writes the queue to a playlist file, `export!` replaces an existing
+    /// one.
+    Export {
+        path: PathBuf,
+        overwrite: bool,
+    },
+    Quit,
+    /// Saves the queue as a playlist.
+    Save(String),
+    Seek(Adjust<Duration>),
+    /// None toggles shuffling.
+    Shuffle(Option<bool>),
+    Theme(String),
+    /// Volume in percent.
+    Volume(Adjust<u8>),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> color_eyre::Result<Command> {
+        let line = line.trim();
+        let (name, argument) = line
+            .split_once(char::is_whitespace)
+            .map(|(name, argument)| (name, argument.trim()))
+            .unwrap_or((line, ""));
+
+        let required = || match argument {
+            "" => Err(eyre!("Usage: {name} {}", usage(name))),
+            argument => Ok(argument),
+        };
+
+        Ok(match name {
+            "add" => Command::Add(expand_home(required()?)),
+            "cd" => Command::Cd(expand_home(match argument {
+                "" => "~",
+                argument => argument,
+            })),
+            "export" | "export!" => Command::Export {
+                path: expand_home(required()?),
+                overwrite: name.ends_with('!'),
+            },
+            "q" | "quit" => Command::Quit,
+            "save" => Command::Save(required()?.to_string()),
+            "seek" => Command::Seek(parse_adjust(required()?, parse_time)?),
+            "shuffle" => Command::Shuffle(match argument {
+                "" | "toggle" => None,
+                "on" => Some(true),
+                "off" => Some(false),
+                _ => return Err(eyre!("Usage: shuffle {}", usage("shuffle"))),
+            }),
+            "theme" => Command::Theme(required()?.to_string()),
+            "vol" | "volume" => Command::Volume(parse_adjust(required()?, |value| {
+                value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|volume| *volume <= 100)
+                    .ok_or_else(|| eyre!("Volume must be between 0 and 100."))
+            })?),
+            _ => return Err(eyre!("Unknown command \"{name}\".")),
+        })
+    }
+}
+
+fn usage(name: &str) -> &'static str {
+    let name = name.trim_end_matches('!');
+
+    COMMANDS
+        .iter()
+        .find(|(command, _)| *command == name)
+        .map(|(_, usage)| *usage)
+        .unwrap_or_default()
+}
+
+/// A leading + or - makes the value relative.
+fn parse_adjust<T>(
+    argument: &str,
+    parse: impl Fn(&str) -> color_eyre::Result<T>,
+) -> color_eyre::Result<Adjust<T>> {
+    Ok(if let Some(value) = argument.strip_prefix('+') {
+        Adjust::Up(parse(value)?)
+    } else if let Some(value) = argument.strip_prefix('-') {
+        Adjust::Down(parse(value)?)
+    } else {
+        Adjust::To(parse(argument)?)
+    })
+}
+
+/// Seconds, `m:ss` or `h:mm:ss`, the seconds may have a fraction.
+fn parse_time(time: &str) -> color_eyre::Result<Duration> {
+    let invalid = || eyre!("Invalid time \"{time}\", use seconds or m:ss.");
+    let parts: Vec<&str> = time.split(':').collect();
+
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let (seconds, rest) = parts.split_last().ok_or_else(invalid)?;
+    let mut minutes: u64 = 0;
+
+    for part in rest {
+        let part = part.parse::<u64>().map_err(|_| invalid())?;
+        minutes = minutes
+            .checked_mul(60)
+            .and_then(|minutes| minutes.checked_add(part))
+            .ok_or_else(invalid)?;
+    }
+
+    let seconds = seconds.parse::<f64>().map_err(|_| invalid())?;
+    let seconds = Duration::try_from_secs_f64(seconds).map_err(|_| invalid())?;
+
+    minutes
+        .checked_mul(60)
+        .and_then(|minutes| Duration::from_secs(minutes).checked_add(seconds))
+        .ok_or_else(invalid)
+}
+
+/// Replaces a leading `~` with the home directory.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The `:` command line at the bottom of the screen, with completion and a
+/// history that is kept between sessions.
+pub struct CommandLine {
+    input: Option<String>,
+    history: Vec<String>,
+    /// Entry shown while going through the history, None for a new line.
+    history_index: Option<usize>,
+    /// Result of the last command, or the candidates of a completion.
+    status: Option<String>,
+    /// Whether the status is an error message.
+    error: bool,
+}
+
+impl CommandLine {
+    pub fn new() -> CommandLine {
+        CommandLine {
+            input: None,
+            history: load_history().unwrap_or_default(),
+            history_index: None,
+            status: None,
+            error: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn open(&mut self) {
+        self.open_with("");
+    }
+
+    /// Opens the command line with `text` already typed.
+    pub fn open_with(&mut self, text: &str) {
+        self.input = Some(text.to_string());
+        self.history_index = None;
+        self.set_status(None);
+    }
+
+    pub fn get_input(&self) -> Option<&str> {
+        self.input.as_deref()
+    }
+
+    pub fn get_status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.error
+    }
+
+    pub fn set_status(&mut self, status: Option<String>) {
+        self.status = status;
+        self.error = false;
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.status = Some(error);
+        self.error = true;
+    }
+
+    /// Edits the line, returns it once submitted. Relative paths are
+    /// completed from `dir`.
+    pub fn handle_key_event(&mut self, key_event: KeyEvent, dir: &Path) -> Option<String> {
+        if self.input.is_some() && key_event.code != KeyCode::Tab {
+            self.set_status(None);
+        }
+
+        let input = self.input.as_mut()?;
+
+        match key_event.code {
+            KeyCode::Char(c) => input.push(c),
+            KeyCode::Backspace if input.is_empty() => self.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Tab => self.complete(dir),
+            KeyCode::Up => self.browse_history(true),
+            KeyCode::Down => self.browse_history(false),
+            KeyCode::Enter => {
+                let line = self.input.take()?.trim().to_string();
+
+                if !line.is_empty() {
+                    self.history.retain(|entry| *entry != line);
+                    self.history.push(line.clone());
+
+                    let excess = self.history.len().saturating_sub(HISTORY_LEN);
+                    self.history.drain(..excess);
+
+                    // Losing the history isn't worth interrupting the command.
+                    let _ = save_history(&self.history);
+                }
+
+                return Some(line);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) => self.history.len().checked_sub(1),
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) => Some(index + 1).filter(|index| *index < self.history.len()),
+        };
+
+        self.history_index = index;
+        self.input = Some(
+            index
+                .map(|index| self.history[index].clone())
+                .unwrap_or_default(),
+        );
+    }
+
+    /// Completes the command name, or a path or value for its argument, as
+    /// far as the candidates agree. Ambiguous candidates are listed in the
+    /// status line.
+    fn complete(&mut self, dir: &Path) {
+        let Some(input) = &self.input else {
+            return;
+        };
+
+        let (kept, typed, candidates) = match input.split_once(' ') {
+            None => (
+                String::new(),
+                input.clone(),
+                COMMANDS
+                    .iter()
+                    .map(|(name, _)| format!("{name} "))
+                    .collect(),
+            ),
+            Some((name @ ("add" | "cd" | "export" | "export!"), argument)) => {
+                let split = argument.rfind('/').map_or(0, |i| i + 1);
+                let (parent, file) = argument.split_at(split);
+
+                (
+                    format!("{name} {parent}"),
+                    file.to_string(),
+                    list_dir(&dir.join(expand_home(parent)), name == "cd"),
+                )
+            }
+            Some(("shuffle", argument)) => (
+                String::from("shuffle "),
+                argument.to_string(),
+                vec![
+                    String::from("on"),
+                    String::from("off"),
+                    String::from("toggle"),
+                ],
+            ),
+            Some(("theme", argument)) => {
+                (String::from("theme "), argument.to_string(), Theme::list())
+            }
+            Some(_) => return,
+        };
+
+        let candidates: Vec<String> = candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(&typed))
+            .filter(|candidate| typed.starts_with('.') || !candidate.starts_with('.'))
+            .collect();
+
+        let Some(first) = candidates.first() else {
+            self.set_status(Some(String::from("No completions.")));
+            return;
+        };
+
+        let common = candidates.iter().fold(first.as_str(), |common, candidate| {
+            let length = common
+                .char_indices()
+                .zip(candidate.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(candidate.len()), |((i, _), _)| i);
+
+            &common[..length]
+        });
+
+        self.set_status((candidates.len() > 1).then(|| {
+            candidates
+                .iter()
+                .map(|candidate| candidate.trim_end())
+                .collect::<Vec<&str>>()
+                .join("  ")
+        }));
+        self.input = Some(format!("{kept}{common}"));
+    }
+}
+
+/// Names of the entries of `dir`, directories end with a slash.
+fn list_dir(dir: &Path, only_dirs: bool) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+
+            match entry.path().is_dir() {
+                true => Some(format!("{name}/")),
+                false if only_dirs => None,
+                false => Some(name),
+            }
+        })
+        .collect();
+
+    names.sort();
+    names
+}
+
+fn history_path() -> color_eyre::Result<PathBuf> {
+    let mut path = paths::data_dir()?;
+    path.push("command_history");
+    Ok(path)
+}
+
+fn load_history() -> color_eyre::Result<Vec<String>> {
+    Ok(fs::read_to_string(history_path()?)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn save_history(history: &[String]) -> color_eyre::Result<()> {
+    let mut content = history.join("\n");
+    content.push('\n');
+
+    fs::write(history_path()?, content)?;
+
+    Ok(())
+}
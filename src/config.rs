@@ -0,0 +1,210 @@
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::{eq::EqSettings, paths};
+
+/// Longest crossfade accepted from the config file.
+const MAX_CROSSFADE: f32 = 12.0;
+
+/// Settings read from `config.toml` in the config directory. Missing keys
+/// fall back to their defaults, a missing file gives the default config.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub playback: PlaybackConfig,
+    pub equalizer: EqualizerConfig,
+    pub output: OutputConfig,
+    pub appearance: AppearanceConfig,
+    /// Remapped keys by context and action, e.g. `[keys.global]` with
+    /// `skip = "n"`.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub keys: HashMap<String, HashMap<String, String>>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PlaybackConfig {
+    /// Seconds the end of a track overlaps the start of the next one, 0
+    /// turns crossfading off.
+    pub crossfade: f32,
+    pub replay_gain: ReplayGainMode,
+    /// Extra gain in dB added to the ReplayGain adjustment.
+    pub preamp: f32,
+    /// Lowers the gain where the track's peak would otherwise clip.
+    pub clipping_prevention: bool,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> PlaybackConfig {
+        PlaybackConfig {
+            crossfade: 0.0,
+            replay_gain: ReplayGainMode::Auto,
+            preamp: 0.0,
+            clipping_prevention: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+    /// Album gain while consecutive tracks of one album play, track gain
+    /// otherwise.
+    #[default]
+    Auto,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EqualizerConfig {
+    /// Equalizer settings by output device name.
+    pub profiles: HashMap<String, EqSettings>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub backend: OutputBackend,
+    /// Name of the output device, the system default when unset or not
+    /// connected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// File the WAV backend records to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputBackend {
+    /// A sound card.
+    #[default]
+    Device,
+    /// Plays in real time without making a sound, for machines without
+    /// audio hardware.
+    Null,
+    /// Records what would be played into a WAV file.
+    Wav,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AppearanceConfig {
+    /// A built-in theme, or the name of a file in the `themes` directory
+    /// of the config directory without `.toml`.
+    pub theme: String,
+}
+
+impl Default for AppearanceConfig {
+    fn default() -> AppearanceConfig {
+        AppearanceConfig {
+            theme: String::from("default"),
+        }
+    }
+}
+
+impl PlaybackConfig {
+    pub fn get_crossfade(&self) -> Duration {
+        Duration::try_from_secs_f32(self.crossfade.clamp(0.0, MAX_CROSSFADE)).unwrap_or_default()
+    }
+}
+
+impl Config {
+    pub fn load() -> color_eyre::Result<Config> {
+        let path = config_path()?;
+
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| eyre!("Invalid config file {}. {e}", path.display()))
+    }
+
+    /// Writes the settings into the existing file, the user's comments and
+    /// layout stay where the values didn't change.
+    pub fn save(&self) -> color_eyre::Result<()> {
+        let path = config_path()?;
+        let content =
+            toml::to_string_pretty(self).map_err(|e| eyre!("Failed to write config. {e}"))?;
+
+        let document = fs::read_to_string(&path)
+            .ok()
+            .and_then(|old| old.parse::<DocumentMut>().ok());
+
+        let Some(mut document) = document else {
+            fs::write(path, content)?;
+            return Ok(());
+        };
+
+        let mut new = content
+            .parse::<DocumentMut>()
+            .map_err(|e| eyre!("Failed to write config. {e}"))?
+            .into_table();
+
+        // Tables that aren't in the file yet go after the existing ones.
+        let mut last = 0;
+        for_each_table(document.as_table_mut(), &mut |table| {
+            last = last.max(table.position().unwrap_or_default());
+        });
+        for_each_table(&mut new, &mut |table| {
+            if let Some(position) = table.position() {
+                table.set_position(last + 1 + position);
+            }
+        });
+
+        merge(document.as_table_mut(), new);
+        fs::write(path, document.to_string())?;
+
+        Ok(())
+    }
+}
+
+fn for_each_table(table: &mut Table, f: &mut impl FnMut(&mut Table)) {
+    f(table);
+
+    let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+
+    for key in keys {
+        match table.get_mut(&key) {
+            Some(Item::Table(table)) => for_each_table(table, f),
+            Some(Item::ArrayOfTables(array)) => {
+                for table in array.iter_mut() {
+                    for_each_table(table, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Makes `old` hold the values of `new`, keeping the comments of the keys
+/// and values that are still there.
+fn merge(old: &mut Table, new: Table) {
+    old.retain(|key, _| new.contains_key(key));
+
+    for (key, item) in new {
+        match (old.get_mut(&key), item) {
+            (Some(Item::Table(old)), Item::Table(new)) => merge(old, new),
+            (Some(Item::Value(old)), Item::Value(mut new)) => {
+                *new.decor_mut() = old.decor().clone();
+                *old = new;
+            }
+            (Some(old), new) => *old = new,
+            (None, new) => {
+                old.insert(&key, new);
+            }
+        }
+    }
+}
+
+fn config_path() -> color_eyre::Result<PathBuf> {
+    let mut path = paths::config_dir()?;
+    path.push("config.toml");
+    Ok(path)
+}
@@ -0,0 +1,190 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{playlist, song::Song};
+
+/// CUE sheets address positions in CD frames, 75 per second.
+const FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Clone)]
+pub struct CueTrack {
+    number: u32,
+    title: Option<String>,
+    performer: Option<String>,
+    file: PathBuf,
+    start: Duration,
+    end: Option<Duration>,
+}
+
+impl CueTrack {
+    pub fn get_number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn get_file(&self) -> PathBuf {
+        self.file.clone()
+    }
+
+    /// Name of the virtual entry shown in the browser.
+    pub fn get_display_name(&self) -> String {
+        match &self.title {
+            Some(title) => format!("{:02}. {}", self.number, title.replace('/', "-")),
+            None => format!("{:02}. Track {}", self.number, self.number),
+        }
+    }
+
+    pub fn into_song(self) -> Song {
+        let mut song = Song::new(self.file);
+
+        song.set_title(
+            self.title
+                .unwrap_or_else(|| format!("Track {}", self.number)),
+        );
+
+        if let Some(performer) = self.performer {
+            song.set_artist(performer);
+        }
+
+        song.set_segment(self.start, self.end);
+
+        song
+    }
+}
+
+pub fn is_cue(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+        && path.is_file()
+}
+
+/// Returns the track number of a virtual entry produced by
+/// [`CueTrack::get_display_name`] below a CUE sheet path.
+pub fn parse_virtual_entry(path: &Path) -> Option<(PathBuf, u32)> {
+    let cue = path.parent()?;
+
+    if !is_cue(cue) {
+        return None;
+    }
+
+    let name = path.file_name()?.to_str()?;
+    let (number, _) = name.split_once('.')?;
+
+    Some((cue.to_path_buf(), number.parse().ok()?))
+}
+
+pub fn read(path: &Path) -> color_eyre::Result<Vec<CueTrack>> {
+    let content = playlist::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new("/"));
+
+    let mut tracks: Vec<CueTrack> = vec![];
+    let mut album_performer: Option<String> = None;
+    let mut file: Option<PathBuf> = None;
+    let mut in_track = false;
+
+    for line in content.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+
+        match command.to_uppercase().as_str() {
+            "FILE" => {
+                // FILE "name.flac" WAVE, the file type is irrelevant here.
+                let name = match args.rsplit_once(' ') {
+                    Some((name, _)) => unquote(name),
+                    None => unquote(args),
+                };
+
+                file = playlist::resolve_location(base, &name);
+                in_track = false;
+            }
+            "TRACK" => {
+                let Some(file) = &file else {
+                    continue;
+                };
+
+                let number = args
+                    .split_whitespace()
+                    .next()
+                    .and_then(|number| number.parse().ok())
+                    .unwrap_or(tracks.len() as u32 + 1);
+
+                tracks.push(CueTrack {
+                    number,
+                    title: None,
+                    performer: album_performer.clone(),
+                    file: file.clone(),
+                    start: Duration::ZERO,
+                    end: None,
+                });
+
+                in_track = true;
+            }
+            "TITLE" if in_track => {
+                if let Some(track) = tracks.last_mut() {
+                    track.title = Some(unquote(args));
+                }
+            }
+            "PERFORMER" => {
+                if in_track {
+                    if let Some(track) = tracks.last_mut() {
+                        track.performer = Some(unquote(args));
+                    }
+                } else {
+                    album_performer = Some(unquote(args));
+                }
+            }
+            "INDEX" if in_track => {
+                let mut parts = args.split_whitespace();
+
+                // INDEX 00 is the pregap, playback starts at INDEX 01.
+                if parts.next() == Some("01")
+                    && let Some(start) = parts.next().and_then(parse_timestamp)
+                    && let Some(track) = tracks.last_mut()
+                {
+                    track.start = start;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // A track ends where the next track in the same file begins, the last
+    // track of a file plays until the end.
+    for i in 0..tracks.len().saturating_sub(1) {
+        if tracks[i].file == tracks[i + 1].file {
+            tracks[i].end = Some(tracks[i + 1].start);
+        }
+    }
+
+    Ok(tracks)
+}
+
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+
+    text.strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .unwrap_or(text)
+        .to_string()
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    // mm:ss:ff
+    let mut parts = timestamp.split(':');
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    let seconds: u64 = parts.next()?.parse().ok()?;
+    let frames: u64 = parts.next()?.parse().ok()?;
+
+    // A malformed sheet may hold any number, None instead of overflowing.
+    let frames = minutes
+        .checked_mul(60)?
+        .checked_add(seconds)?
+        .checked_mul(FRAMES_PER_SECOND)?
+        .checked_add(frames)?;
+
+    Some(Duration::from_nanos(
+        frames.checked_mul(1_000_000_000)? / FRAMES_PER_SECOND,
+    ))
+}
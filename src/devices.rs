@@ -0,0 +1,91 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::{
+    config::{Config, OutputBackend},
+    output,
+    player_controller::PlayerController,
+};
+
+/// State of the output device picker. The first entry stands for the
+/// system default, the rest are the devices found when the window was
+/// last refreshed.
+pub struct Devices {
+    devices: Vec<String>,
+    selected: usize,
+    status: Option<String>,
+}
+
+impl Devices {
+    pub fn new() -> Devices {
+        Devices {
+            devices: output::list_devices(),
+            selected: 0,
+            status: None,
+        }
+    }
+
+    /// Entries as shown in the window.
+    pub fn get_entries(&self) -> Vec<String> {
+        std::iter::once(String::from("System default"))
+            .chain(self.devices.iter().cloned())
+            .collect()
+    }
+
+    pub fn get_selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn set_selected(&mut self, selected: usize) {
+        self.selected = selected.min(self.devices.len());
+    }
+
+    pub fn get_status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    pub fn handle_key_event(
+        &mut self,
+        key_event: KeyEvent,
+        config: &mut Config,
+        pc: &mut PlayerController,
+    ) -> color_eyre::Result<()> {
+        self.status = None;
+
+        match key_event.code {
+            KeyCode::Char('h') => self.selected = 0,
+            KeyCode::Char('j') if self.selected < self.devices.len() => self.selected += 1,
+            KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('l') => self.selected = self.devices.len(),
+            KeyCode::Char('r') => self.refresh(),
+            KeyCode::Enter => {
+                let device = self
+                    .selected
+                    .checked_sub(1)
+                    .and_then(|index| self.devices.get(index).cloned());
+
+                pc.set_device(device.clone())?;
+
+                config.output.backend = OutputBackend::Device;
+                config.output.device = device;
+                config.save()?;
+
+                self.status = Some(format!(
+                    "Switched to {}.",
+                    config
+                        .output
+                        .device
+                        .as_deref()
+                        .unwrap_or("the system default")
+                ));
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn refresh(&mut self) {
+        self.devices = output::list_devices();
+        self.selected = self.selected.min(self.devices.len());
+    }
+}
@@ -0,0 +1,363 @@
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::{
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Center frequencies of the graphic equalizer bands.
+pub const GRAPHIC_FREQUENCIES: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// One octave wide bands.
+const GRAPHIC_Q: f32 = 1.41;
+
+pub const MAX_GAIN: f32 = 12.0;
+
+/// Sample frames between two coefficient updates.
+const UPDATE_INTERVAL: usize = 64;
+
+/// Largest gain change per update, changes are spread over several updates
+/// so moving a band doesn't click.
+const GAIN_STEP: f32 = 0.25;
+
+#[derive(Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Band {
+    pub frequency: f32,
+    pub gain: f32,
+    pub q: f32,
+}
+
+impl Band {
+    pub fn new(frequency: f32) -> Band {
+        Band {
+            frequency,
+            gain: 0.0,
+            q: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EqSettings {
+    pub enabled: bool,
+    /// Gains in dB of the bands in [`GRAPHIC_FREQUENCIES`].
+    pub graphic: Vec<f32>,
+    pub parametric: Vec<Band>,
+}
+
+impl Default for EqSettings {
+    fn default() -> EqSettings {
+        EqSettings {
+            enabled: false,
+            graphic: vec![0.0; GRAPHIC_FREQUENCIES.len()],
+            parametric: vec![],
+        }
+    }
+}
+
+impl EqSettings {
+    /// Every band to run, all flat while the equalizer is disabled.
+    fn bands(&self) -> Vec<Band> {
+        let graphic = GRAPHIC_FREQUENCIES
+            .iter()
+            .zip(self.graphic.iter().chain(std::iter::repeat(&0.0)))
+            .map(|(&frequency, &gain)| Band {
+                frequency,
+                gain,
+                q: GRAPHIC_Q,
+            });
+
+        graphic
+            .chain(self.parametric.iter().copied())
+            .map(|band| Band {
+                gain: if self.enabled { band.gain } else { 0.0 },
+                ..band
+            })
+            .collect()
+    }
+
+    /// Gain in dB that keeps the loudest boost from clipping.
+    fn preamp(&self) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+
+        -self
+            .graphic
+            .iter()
+            .chain(self.parametric.iter().map(|band| &band.gain))
+            .fold(0.0, |max: f32, gain| max.max(*gain))
+    }
+}
+
+pub struct Preset {
+    pub name: &'static str,
+    pub gains: [f32; 10],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "Flat",
+        gains: [0.0; 10],
+    },
+    Preset {
+        name: "Bass Boost",
+        gains: [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    },
+    Preset {
+        name: "Treble Boost",
+        gains: [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 4.0, 5.0, 6.0],
+    },
+    Preset {
+        name: "Rock",
+        gains: [4.0, 3.0, 2.0, 0.0, -1.0, -1.0, 0.0, 2.0, 3.0, 4.0],
+    },
+    Preset {
+        name: "Pop",
+        gains: [-1.0, 0.0, 2.0, 3.0, 4.0, 3.0, 2.0, 0.0, -1.0, -1.0],
+    },
+    Preset {
+        name: "Jazz",
+        gains: [3.0, 2.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0],
+    },
+    Preset {
+        name: "Classical",
+        gains: [4.0, 3.0, 2.0, 1.0, 0.0, 0.0, 0.0, 1.0, 2.0, 3.0],
+    },
+    Preset {
+        name: "Vocal",
+        gains: [-2.0, -2.0, -1.0, 1.0, 3.0, 4.0, 3.0, 1.0, 0.0, -1.0],
+    },
+];
+
+#[derive(Default)]
+struct Shared {
+    settings: Mutex<EqSettings>,
+    generation: AtomicU64,
+}
+
+/// Changes the settings of a running [`Equalize`] source.
+#[derive(Clone, Default)]
+pub struct EqHandle {
+    shared: Arc<Shared>,
+}
+
+impl EqHandle {
+    pub fn set(&self, settings: EqSettings) {
+        if let Ok(mut current) = self.shared.settings.lock() {
+            *current = settings;
+            self.shared.generation.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn bands(&self) -> Vec<Band> {
+        self.shared
+            .settings
+            .lock()
+            .map(|settings| settings.bands())
+            .unwrap_or_default()
+    }
+
+    fn preamp(&self) -> f32 {
+        self.shared
+            .settings
+            .lock()
+            .map(|settings| settings.preamp())
+            .unwrap_or_default()
+    }
+
+    fn generation(&self) -> u64 {
+        self.shared.generation.load(Ordering::SeqCst)
+    }
+}
+
+/// Peaking filter from the Audio EQ Cookbook, transposed direct form II.
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn set_peaking(&mut self, band: &Band, sample_rate: u32) {
+        let frequency = band.frequency.min(sample_rate as f32 * 0.45);
+        let a = 10f32.powf(band.gain / 40.0);
+        let w0 = 2.0 * PI * frequency / sample_rate as f32;
+        let alpha = w0.sin() / (2.0 * band.q.max(0.1));
+        let cos = w0.cos();
+
+        let a0 = 1.0 + alpha / a;
+
+        self.b0 = (1.0 + alpha * a) / a0;
+        self.b1 = -2.0 * cos / a0;
+        self.b2 = (1.0 - alpha * a) / a0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha / a) / a0;
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+/// Runs the samples of `inner` through the equalizer bands set on the
+/// handle. Settings are picked up while playing, the filter state is kept
+/// so the sound changes without interruption.
+pub struct Equalize<S> {
+    inner: S,
+    handle: EqHandle,
+    generation: u64,
+    target: Vec<Band>,
+    current: Vec<Band>,
+    /// In dB, it follows its target in steps like the bands.
+    target_preamp: f32,
+    preamp: f32,
+    /// Linear factor of `preamp`.
+    preamp_factor: f32,
+    /// One filter per band and channel.
+    filters: Vec<Vec<Biquad>>,
+    channel: usize,
+    until_update: usize,
+}
+
+impl<S: Source> Equalize<S> {
+    pub fn new(inner: S, handle: EqHandle) -> Equalize<S> {
+        Equalize {
+            inner,
+            handle,
+            generation: u64::MAX,
+            target: vec![],
+            current: vec![],
+            target_preamp: 0.0,
+            preamp: 0.0,
+            preamp_factor: 1.0,
+            filters: vec![],
+            channel: 0,
+            until_update: 0,
+        }
+    }
+
+    fn update(&mut self) {
+        let generation = self.handle.generation();
+
+        if generation != self.generation {
+            self.generation = generation;
+            self.target = self.handle.bands();
+            self.target_preamp = self.handle.preamp();
+
+            let channels = self.inner.channels() as usize;
+
+            // New bands start flat and fade in like any other change.
+            self.current.truncate(self.target.len());
+            self.filters.truncate(self.target.len());
+
+            for band in &self.target[self.current.len()..] {
+                let band = Band { gain: 0.0, ..*band };
+                let mut filter = Biquad::default();
+                filter.set_peaking(&band, self.inner.sample_rate());
+
+                self.current.push(band);
+                self.filters.push(vec![filter; channels]);
+            }
+        }
+
+        let sample_rate = self.inner.sample_rate();
+
+        for ((current, target), filters) in self
+            .current
+            .iter_mut()
+            .zip(&self.target)
+            .zip(&mut self.filters)
+        {
+            if current == target {
+                continue;
+            }
+
+            let delta = target.gain - current.gain;
+
+            if delta.abs() <= GAIN_STEP {
+                current.gain = target.gain;
+            } else {
+                current.gain += delta.signum() * GAIN_STEP;
+            }
+            current.frequency = target.frequency;
+            current.q = target.q;
+
+            for filter in filters.iter_mut() {
+                filter.set_peaking(current, sample_rate);
+            }
+        }
+
+        if self.preamp != self.target_preamp {
+            let delta = self.target_preamp - self.preamp;
+
+            if delta.abs() <= GAIN_STEP {
+                self.preamp = self.target_preamp;
+            } else {
+                self.preamp += delta.signum() * GAIN_STEP;
+            }
+
+            self.preamp_factor = 10f32.powf(self.preamp / 20.0);
+        }
+    }
+}
+
+impl<S: Source> Iterator for Equalize<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.channel == 0 {
+            if self.until_update == 0 {
+                self.update();
+                self.until_update = UPDATE_INTERVAL;
+            }
+
+            self.until_update -= 1;
+        }
+
+        let mut sample = self.inner.next()? * self.preamp_factor;
+
+        for filters in &mut self.filters {
+            if let Some(filter) = filters.get_mut(self.channel) {
+                sample = filter.process(sample);
+            }
+        }
+
+        self.channel = (self.channel + 1) % self.inner.channels().max(1) as usize;
+
+        Some(sample)
+    }
+}
+
+impl<S: Source> Source for Equalize<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        self.inner.current_span_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
@@ -0,0 +1,196 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::{
+    config::Config,
+    eq::{Band, EqHandle, EqSettings, GRAPHIC_FREQUENCIES, MAX_GAIN, PRESETS},
+    output,
+};
+
+/// Frequency step of a parametric band, a sixth of an octave.
+const FREQUENCY_STEP: f32 = 1.122_462;
+
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20_000.0;
+
+/// State of the equalizer window. Every change is sent to the player right
+/// away, `w` stores the settings as the profile of the current device.
+pub struct Equalizer {
+    device: String,
+    settings: EqSettings,
+    handle: EqHandle,
+    /// Graphic bands first, then the parametric ones.
+    selected: usize,
+    preset: Option<usize>,
+    status: Option<String>,
+}
+
+impl Equalizer {
+    pub fn new(config: &Config) -> Equalizer {
+        let device = config
+            .output
+            .device
+            .clone()
+            .unwrap_or_else(output::default_device_name);
+
+        let mut equalizer = Equalizer {
+            device: String::new(),
+            settings: EqSettings::default(),
+            handle: EqHandle::default(),
+            selected: 0,
+            preset: None,
+            status: None,
+        };
+
+        equalizer.set_device(device, config);
+
+        equalizer
+    }
+
+    /// Loads the profile of the device the player switched to.
+    pub fn set_device(&mut self, device: String, config: &Config) {
+        let mut settings = config
+            .equalizer
+            .profiles
+            .get(&device)
+            .cloned()
+            .unwrap_or_default();
+
+        settings.graphic.resize(GRAPHIC_FREQUENCIES.len(), 0.0);
+
+        self.handle.set(settings.clone());
+
+        self.device = device;
+        self.settings = settings;
+        self.selected = 0;
+        self.preset = None;
+    }
+
+    pub fn get_handle(&self) -> EqHandle {
+        self.handle.clone()
+    }
+
+    pub fn get_device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn get_settings(&self) -> &EqSettings {
+        &self.settings
+    }
+
+    pub fn get_selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn get_preset_name(&self) -> Option<&'static str> {
+        self.preset.map(|preset| PRESETS[preset].name)
+    }
+
+    pub fn get_status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    /// Every band as shown in the window.
+    pub fn get_bands(&self) -> Vec<Band> {
+        GRAPHIC_FREQUENCIES
+            .iter()
+            .zip(&self.settings.graphic)
+            .map(|(&frequency, &gain)| Band {
+                gain,
+                ..Band::new(frequency)
+            })
+            .chain(self.settings.parametric.iter().copied())
+            .collect()
+    }
+
+    pub fn handle_key_event(
+        &mut self,
+        key_event: KeyEvent,
+        config: &mut Config,
+    ) -> color_eyre::Result<()> {
+        self.status = None;
+
+        let bands = GRAPHIC_FREQUENCIES.len() + self.settings.parametric.len();
+
+        match key_event.code {
+            KeyCode::Char('h') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('l') => self.selected = (self.selected + 1).min(bands - 1),
+            KeyCode::Char('k') => self.change_gain(1.0),
+            KeyCode::Char('j') => self.change_gain(-1.0),
+            KeyCode::Char('K') => self.change_frequency(FREQUENCY_STEP),
+            KeyCode::Char('J') => self.change_frequency(1.0 / FREQUENCY_STEP),
+            KeyCode::Char(']') => self.change_q(0.1),
+            KeyCode::Char('[') => self.change_q(-0.1),
+            KeyCode::Char('e') => self.settings.enabled = !self.settings.enabled,
+            KeyCode::Char('n') => self.next_preset(),
+            KeyCode::Char('a') => {
+                self.settings.parametric.push(Band::new(1000.0));
+                self.selected = bands;
+            }
+            KeyCode::Char('d') => {
+                if let Some(index) = self.parametric_index() {
+                    self.settings.parametric.remove(index);
+                    self.selected -= 1;
+                }
+            }
+            KeyCode::Char('w') => {
+                config
+                    .equalizer
+                    .profiles
+                    .insert(self.device.clone(), self.settings.clone());
+                config.save()?;
+
+                self.status = Some(format!("Saved profile for \"{}\".", self.device));
+            }
+            _ => return Ok(()),
+        }
+
+        self.handle.set(self.settings.clone());
+
+        Ok(())
+    }
+
+    fn parametric_index(&self) -> Option<usize> {
+        self.selected
+            .checked_sub(GRAPHIC_FREQUENCIES.len())
+            .filter(|&index| index < self.settings.parametric.len())
+    }
+
+    fn change_gain(&mut self, delta: f32) {
+        let gain = match self.parametric_index() {
+            Some(index) => &mut self.settings.parametric[index].gain,
+            None => &mut self.settings.graphic[self.selected],
+        };
+
+        *gain = (*gain + delta).clamp(-MAX_GAIN, MAX_GAIN);
+        self.settings.enabled = true;
+        self.preset = None;
+    }
+
+    fn change_frequency(&mut self, factor: f32) {
+        match self.parametric_index() {
+            Some(index) => {
+                let band = &mut self.settings.parametric[index];
+                band.frequency = (band.frequency * factor).clamp(MIN_FREQUENCY, MAX_FREQUENCY);
+            }
+            None => self.status = Some(String::from("Only parametric bands can be moved.")),
+        }
+    }
+
+    fn change_q(&mut self, delta: f32) {
+        match self.parametric_index() {
+            Some(index) => {
+                let band = &mut self.settings.parametric[index];
+                band.q = (band.q + delta).clamp(0.1, 10.0);
+            }
+            None => self.status = Some(String::from("Only parametric bands have an adjustable Q.")),
+        }
+    }
+
+    fn next_preset(&mut self) {
+        let preset = self.preset.map_or(0, |preset| (preset + 1) % PRESETS.len());
+
+        self.settings.graphic = PRESETS[preset].gains.to_vec();
+        self.settings.enabled = true;
+        self.preset = Some(preset);
+    }
+}
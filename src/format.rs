@@ -0,0 +1,360 @@
+use audiotags::Tag;
+use color_eyre::eyre::eyre;
+use mp4ameta::FreeformIdent;
+use rodio::{Decoder, Source};
+
+use crate::gapless::Trim;
+use std::{fs::File, io::BufReader, path::Path};
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, StandardTagKey, Tag as SymphoniaTag},
+    probe::Hint,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// Decoded by rodio through symphonia.
+    Rodio,
+    /// rodio has no Opus decoder, these files go through libopus.
+    #[cfg(feature = "opus")]
+    Opus,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TagReader {
+    Audiotags,
+    Symphonia,
+}
+
+pub struct AudioFormat {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    backend: Backend,
+    tag_reader: TagReader,
+}
+
+/// Every format the player can decode. The browser, the library and the
+/// player all go through this list, a file is either supported everywhere or
+/// nowhere.
+const FORMATS: &[AudioFormat] = &[
+    AudioFormat {
+        name: "MP3",
+        extensions: &["mp3"],
+        backend: Backend::Rodio,
+        tag_reader: TagReader::Audiotags,
+    },
+    AudioFormat {
+        name: "FLAC",
+        extensions: &["flac"],
+        backend: Backend::Rodio,
+        tag_reader: TagReader::Audiotags,
+    },
+    AudioFormat {
+        name: "Ogg Vorbis",
+        extensions: &["ogg", "oga"],
+        backend: Backend::Rodio,
+        tag_reader: TagReader::Symphonia,
+    },
+    #[cfg(feature = "opus")]
+    AudioFormat {
+        name: "Opus",
+        extensions: &["opus"],
+        backend: Backend::Opus,
+        tag_reader: TagReader::Symphonia,
+    },
+    AudioFormat {
+        name: "WAV",
+        extensions: &["wav", "wave"],
+        backend: Backend::Rodio,
+        tag_reader: TagReader::Symphonia,
+    },
+    AudioFormat {
+        name: "AIFF",
+        extensions: &["aiff", "aif", "aifc"],
+        backend: Backend::Rodio,
+        tag_reader: TagReader::Symphonia,
+    },
+    AudioFormat {
+        name: "MPEG-4 Audio (AAC/ALAC)",
+        extensions: &["m4a", "m4b", "mp4"],
+        backend: Backend::Rodio,
+        tag_reader: TagReader::Audiotags,
+    },
+    AudioFormat {
+        name: "AAC",
+        extensions: &["aac"],
+        backend: Backend::Rodio,
+        tag_reader: TagReader::Symphonia,
+    },
+];
+
+impl AudioFormat {
+    pub fn get_name(&self) -> &'static str {
+        self.name
+    }
+}
+
+pub fn from_path(path: &Path) -> Option<&'static AudioFormat> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+
+    FORMATS
+        .iter()
+        .find(|format| format.extensions.contains(&ext.as_str()))
+}
+
+pub fn is_supported(path: &Path) -> bool {
+    from_path(path).is_some()
+}
+
+pub fn open(path: &Path) -> color_eyre::Result<Box<dyn Source + Send>> {
+    let format =
+        from_path(path).ok_or_else(|| eyre!("Unsupported audio format: {}", path.display()))?;
+
+    match format.backend {
+        Backend::Rodio => {
+            let file = File::open(path)?;
+
+            let decoder = Decoder::builder()
+                .with_byte_len(file.metadata()?.len())
+                .with_data(BufReader::new(file))
+                .with_hint(format.extensions[0])
+                .with_seekable(true)
+                // Trims the LAME encoder delay and padding of MP3 files.
+                .with_gapless(true)
+                .build();
+
+            match decoder {
+                Ok(decoder) if format.extensions.contains(&"m4a") => {
+                    Ok(trim_itunes_padding(path, Box::new(decoder)))
+                }
+                Ok(decoder) => Ok(Box::new(decoder)),
+                // .ogg is also used for Opus streams.
+                #[cfg(feature = "opus")]
+                Err(_) if format.extensions.contains(&"ogg") => {
+                    Ok(Box::new(crate::opus::OpusDecoder::open(path)?))
+                }
+                Err(e) => Err(eyre!(
+                    "Failed to decode {} file {}. {e}",
+                    format.name,
+                    path.display()
+                )),
+            }
+        }
+        #[cfg(feature = "opus")]
+        Backend::Opus => Ok(Box::new(crate::opus::OpusDecoder::open(path)?)),
+    }
+}
+
+/// symphonia ignores the iTunSMPB tag of MPEG-4 files, which holds the
+/// encoder delay and the real length of the stream in sample frames.
+fn trim_itunes_padding(path: &Path, source: Box<dyn Source + Send>) -> Box<dyn Source + Send> {
+    let ident = FreeformIdent::new("com.apple.iTunes", "iTunSMPB");
+
+    let Ok(tag) = mp4ameta::Tag::read_from_path(path) else {
+        return source;
+    };
+
+    let Some(smpb) = tag.strings_of(&ident).next() else {
+        return source;
+    };
+
+    // " 00000000 00000840 000001CA 00000000003F31F6 ..." in hex: reserved,
+    // delay, padding, length.
+    let fields: Vec<u64> = smpb
+        .split_whitespace()
+        .filter_map(|field| u64::from_str_radix(field, 16).ok())
+        .collect();
+
+    match fields.as_slice() {
+        [_, delay, _, length, ..] if *length > 0 => {
+            Box::new(Trim::new(source, *delay, Some(*length)))
+        }
+        _ => source,
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub track_number: Option<u16>,
+    pub disc_number: Option<u16>,
+}
+
+pub fn read_tags(path: &Path) -> color_eyre::Result<Tags> {
+    let format =
+        from_path(path).ok_or_else(|| eyre!("Unsupported audio format: {}", path.display()))?;
+
+    match format.tag_reader {
+        TagReader::Audiotags => {
+            let tag = Tag::new()
+                .read_from_path(path)
+                .map_err(|e| eyre!("Failed to read tag. {e}"))?;
+
+            Ok(Tags {
+                title: tag.title().map(|t| t.to_owned()),
+                artist: tag.artist().map(|a| a.to_owned()),
+                album: tag.album_title().map(|a| a.to_owned()),
+                genre: tag.genre().map(|g| g.to_owned()),
+                year: tag.year(),
+                track_number: tag.track_number(),
+                disc_number: tag.disc_number(),
+            })
+        }
+        TagReader::Symphonia => read_symphonia_tags(path),
+    }
+}
+
+/// Genres of content that is listened to rather than played as music.
+const SPOKEN_WORD_GENRES: &[&str] = &[
+    "audiobook",
+    "audio book",
+    "speech",
+    "spoken",
+    "podcast",
+    "lecture",
+];
+
+/// Audiobooks, podcasts and similar, recognised by the genre tag or the
+/// .m4b audiobook container.
+pub fn is_spoken_word(path: &Path) -> bool {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("m4b"))
+    {
+        return true;
+    }
+
+    read_tags(path)
+        .ok()
+        .and_then(|tags| tags.genre)
+        .is_some_and(|genre| {
+            let genre = genre.to_lowercase();
+            SPOKEN_WORD_GENRES
+                .iter()
+                .any(|spoken| genre.contains(spoken))
+        })
+}
+
+/// ReplayGain values in dB and linear peaks, read from
+/// `REPLAYGAIN_*` or Opus style `R128_*` tags.
+#[derive(Clone, Copy, Default)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+pub fn read_replay_gain(path: &Path) -> color_eyre::Result<ReplayGain> {
+    let mut result = ReplayGain::default();
+
+    for tag in probe_tags(path)? {
+        let value = tag.value.to_string();
+
+        // MPEG-4 freeform tags are prefixed with their namespace.
+        let key = tag
+            .key
+            .rsplit(':')
+            .next()
+            .unwrap_or(&tag.key)
+            .to_lowercase();
+
+        match key.as_str() {
+            "replaygain_track_gain" => result.track_gain = parse_gain(&value),
+            "replaygain_track_peak" => result.track_peak = value.trim().parse().ok(),
+            "replaygain_album_gain" => result.album_gain = parse_gain(&value),
+            "replaygain_album_peak" => result.album_peak = value.trim().parse().ok(),
+            // R128 gains are Q7.8 fixed point relative to -23 LUFS, ReplayGain
+            // uses a 5 dB louder reference.
+            "r128_track_gain" => result.track_gain = parse_r128_gain(&value),
+            "r128_album_gain" => result.album_gain = parse_r128_gain(&value),
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+fn parse_gain(value: &str) -> Option<f32> {
+    // "-6.54 dB"
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+
+    value.trim().parse().ok()
+}
+
+fn parse_r128_gain(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .parse::<i16>()
+        .ok()
+        .map(|gain| gain as f32 / 256.0 + 5.0)
+}
+
+fn probe_tags(path: &Path) -> color_eyre::Result<Vec<SymphoniaTag>> {
+    let stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut tags: Vec<SymphoniaTag> = vec![];
+
+    // Tags can live in front of the container (e.g. ID3) or inside of it.
+    if let Some(metadata) = probed.metadata.get()
+        && let Some(revision) = metadata.current()
+    {
+        tags.extend(revision.tags().iter().cloned());
+    }
+
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend(revision.tags().iter().cloned());
+    }
+
+    Ok(tags)
+}
+
+fn read_symphonia_tags(path: &Path) -> color_eyre::Result<Tags> {
+    let mut result = Tags::default();
+
+    for tag in probe_tags(path)? {
+        let value = tag.value.to_string();
+
+        match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => result.title = Some(value),
+            Some(StandardTagKey::Artist) => result.artist = Some(value),
+            Some(StandardTagKey::Album) => result.album = Some(value),
+            Some(StandardTagKey::Genre) => result.genre = Some(value),
+            Some(StandardTagKey::Date) => {
+                result.year = value.get(..4).and_then(|year| year.parse().ok())
+            }
+            Some(StandardTagKey::TrackNumber) => {
+                // "3" or "3/12"
+                result.track_number = value.split('/').next().and_then(|n| n.parse().ok())
+            }
+            Some(StandardTagKey::DiscNumber) => {
+                result.disc_number = value.split('/').next().and_then(|n| n.parse().ok())
+            }
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,575 @@
+use rodio::{
+    source::{SeekError, UniformSourceIterator},
+    Source,
+};
+use std::{
+    collections::VecDeque,
+    f32::consts::FRAC_PI_2,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::song::Song;
+
+pub type Track = Box<dyn Source + Send>;
+
+/// How much of the next track is decoded ahead of time.
+const PREROLL: Duration = Duration::from_millis(500);
+
+/// What the player knows about a queued track, handed back when it starts.
+#[derive(Clone)]
+pub struct TrackDetails {
+    pub song: Song,
+    /// ReplayGain in dB already applied to the source.
+    pub gain: Option<f32>,
+    /// Audiobooks and the like remember their playback speed.
+    pub spoken: bool,
+}
+
+pub enum TrackEvent {
+    /// A track was taken from the upcoming tracks and started playing.
+    Started(TrackDetails),
+    /// A track played to its end or was skipped.
+    Ended,
+}
+
+/// A decoded track waiting in the queue.
+pub struct QueuedTrack {
+    pub source: Track,
+    /// Tracks of the same album are never crossfaded.
+    pub album: Option<String>,
+    pub details: TrackDetails,
+}
+
+/// Where the audio thread is in the current track, readable from anywhere.
+#[derive(Clone, Default)]
+pub struct TrackPosition {
+    position_ms: Arc<AtomicU64>,
+    /// Zero while nothing plays or the length is unknown.
+    length_ms: Arc<AtomicU64>,
+}
+
+impl TrackPosition {
+    pub fn get_position(&self) -> Duration {
+        Duration::from_millis(self.position_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn get_length(&self) -> Option<Duration> {
+        match self.length_ms.load(Ordering::Relaxed) {
+            0 => None,
+            length => Some(Duration::from_millis(length)),
+        }
+    }
+
+    fn set(&self, position: u64, length: Option<u64>, sample_rate: u32) {
+        let ms = |frames: u64| frames * 1000 / sample_rate.max(1) as u64;
+
+        self.position_ms.store(ms(position), Ordering::Relaxed);
+        self.length_ms
+            .store(length.map_or(0, ms), Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    upcoming: Mutex<VecDeque<QueuedTrack>>,
+    /// Set whenever the upcoming tracks change, so the audio thread only
+    /// locks them then.
+    upcoming_changed: AtomicBool,
+    skip: AtomicBool,
+    stop: AtomicBool,
+    crossfade_ms: AtomicU64,
+    /// The current track reopened at another position.
+    seek: Mutex<Option<(Track, Duration)>>,
+    seek_pending: AtomicBool,
+}
+
+/// Player side of a [`GaplessQueue`], used to hand over decoded tracks and
+/// to skip or stop from outside the audio thread.
+#[derive(Clone)]
+pub struct GaplessHandle {
+    shared: Arc<Shared>,
+}
+
+impl GaplessHandle {
+    pub fn push(&self, track: QueuedTrack) {
+        if let Ok(mut upcoming) = self.shared.upcoming.lock() {
+            upcoming.push_back(track);
+            self.shared.upcoming_changed.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Number of tracks waiting behind the current one.
+    pub fn upcoming_len(&self) -> usize {
+        self.shared
+            .upcoming
+            .lock()
+            .map(|upcoming| upcoming.len())
+            .unwrap_or(0)
+    }
+
+    pub fn skip(&self) {
+        self.shared.skip.store(true, Ordering::SeqCst);
+    }
+
+    /// Drops the tracks waiting behind the current one, returns how many
+    /// there were.
+    pub fn clear_upcoming(&self) -> usize {
+        self.shared
+            .upcoming
+            .lock()
+            .map(|mut upcoming| {
+                self.shared.upcoming_changed.store(true, Ordering::SeqCst);
+                upcoming.drain(..).count()
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn stop(&self) {
+        if let Ok(mut upcoming) = self.shared.upcoming.lock() {
+            upcoming.clear();
+            self.shared.upcoming_changed.store(true, Ordering::SeqCst);
+        }
+
+        self.shared.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Replaces the current track with `source`, the same track opened at
+    /// `position`. Nothing happens if the track ended in the meantime.
+    pub fn seek(&self, source: Track, position: Duration) {
+        if let Ok(mut seek) = self.shared.seek.lock() {
+            *seek = Some((source, position));
+            self.shared.seek_pending.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Zero plays the tracks back to back without fading.
+    pub fn set_crossfade(&self, crossfade: Duration) {
+        self.shared
+            .crossfade_ms
+            .store(crossfade.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+struct Current {
+    source: UniformSourceIterator<Track>,
+    album: Option<String>,
+    /// Sample frames played so far.
+    position: u64,
+    /// Length in sample frames, if the decoder knows it.
+    length: Option<u64>,
+}
+
+/// A track fading out while the next one fades in.
+struct Fade {
+    source: UniformSourceIterator<Track>,
+    position: u64,
+    length: u64,
+}
+
+impl Fade {
+    /// Equal power gains of the outgoing and the incoming track.
+    fn gains(&self) -> (f32, f32) {
+        let t = (self.position as f32 / self.length as f32).min(1.0) * FRAC_PI_2;
+        (t.cos(), t.sin())
+    }
+}
+
+/// Plays tracks back to back as one continuous source. Every track is
+/// converted to the output format up front, so the switch to the next track
+/// happens on the very next sample frame instead of going through the sink's
+/// queue. With a crossfade set, the end of a track is mixed with the start of
+/// the next one. Outputs silence while there is nothing to play.
+pub struct GaplessQueue {
+    channels: u16,
+    sample_rate: u32,
+    current: Option<Current>,
+    fade: Option<Fade>,
+    /// Whether the next track is from another album, looked up again only
+    /// when the upcoming tracks change.
+    fade_into_next: bool,
+    shared: Arc<Shared>,
+    position: TrackPosition,
+    /// Position inside the current sample frame, tracks only change on frame
+    /// boundaries.
+    frame_pos: u16,
+    on_event: Box<dyn FnMut(TrackEvent) + Send>,
+}
+
+impl GaplessQueue {
+    pub fn new(
+        channels: u16,
+        sample_rate: u32,
+        position: TrackPosition,
+        on_event: Box<dyn FnMut(TrackEvent) + Send>,
+    ) -> (GaplessQueue, GaplessHandle) {
+        let shared = Arc::new(Shared::default());
+
+        (
+            GaplessQueue {
+                channels,
+                sample_rate,
+                current: None,
+                fade: None,
+                fade_into_next: false,
+                shared: shared.clone(),
+                position,
+                frame_pos: 0,
+                on_event,
+            },
+            GaplessHandle { shared },
+        )
+    }
+
+    fn next_track(&mut self) {
+        let next = self
+            .shared
+            .upcoming
+            .lock()
+            .ok()
+            .and_then(|mut upcoming| upcoming.pop_front());
+
+        if let Some(track) = next {
+            self.shared.upcoming_changed.store(true, Ordering::SeqCst);
+
+            // A seek asked for while the previous track played is stale.
+            self.shared.seek_pending.store(false, Ordering::SeqCst);
+
+            let details = track.details;
+            let length = track
+                .source
+                .total_duration()
+                .map(|duration| (duration.as_secs_f64() * self.sample_rate as f64) as u64);
+
+            self.current = Some(Current {
+                source: UniformSourceIterator::new(track.source, self.channels, self.sample_rate),
+                album: track.album,
+                position: 0,
+                length,
+            });
+
+            (self.on_event)(TrackEvent::Started(details));
+        }
+    }
+
+    fn end_track(&mut self) {
+        if self.current.take().is_some() {
+            (self.on_event)(TrackEvent::Ended);
+        }
+
+        self.next_track();
+    }
+
+    fn apply_seek(&mut self) {
+        let Some((source, position)) = self
+            .shared
+            .seek
+            .lock()
+            .ok()
+            .and_then(|mut seek| seek.take())
+        else {
+            return;
+        };
+
+        if let Some(current) = &mut self.current {
+            current.source = UniformSourceIterator::new(source, self.channels, self.sample_rate);
+            current.position = (position.as_secs_f64() * self.sample_rate as f64) as u64;
+            self.fade = None;
+        }
+    }
+
+    /// Length of the crossfade in sample frames if the current track should
+    /// start fading into the next one now.
+    fn crossfade_due(&mut self) -> Option<u64> {
+        let current = self.current.as_ref()?;
+
+        let crossfade = self.shared.crossfade_ms.load(Ordering::SeqCst);
+        let frames = crossfade * self.sample_rate as u64 / 1000;
+
+        if frames == 0 || self.fade.is_some() {
+            return None;
+        }
+
+        let left = current.length?.checked_sub(current.position)?;
+
+        if left > frames {
+            return None;
+        }
+
+        if self.shared.upcoming_changed.swap(false, Ordering::SeqCst) {
+            let upcoming = self.shared.upcoming.lock().ok()?;
+
+            self.fade_into_next = upcoming
+                .front()
+                .is_some_and(|next| current.album.is_none() || current.album != next.album);
+        }
+
+        self.fade_into_next.then_some(left)
+    }
+
+    fn start_crossfade(&mut self, length: u64) {
+        if let Some(current) = self.current.take() {
+            self.fade = Some(Fade {
+                source: current.source,
+                position: 0,
+                length: length.max(1),
+            });
+
+            (self.on_event)(TrackEvent::Ended);
+        }
+
+        self.next_track();
+    }
+}
+
+impl Iterator for GaplessQueue {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.frame_pos == 0 {
+            if self.shared.stop.swap(false, Ordering::SeqCst) {
+                self.shared.skip.store(false, Ordering::SeqCst);
+                self.current = None;
+                self.fade = None;
+            }
+
+            if self.shared.skip.swap(false, Ordering::SeqCst) {
+                self.fade = None;
+                self.end_track();
+            }
+
+            if self.shared.seek_pending.swap(false, Ordering::SeqCst) {
+                self.apply_seek();
+            }
+
+            if self.current.is_none() {
+                self.next_track();
+            }
+
+            if let Some(length) = self.crossfade_due() {
+                self.start_crossfade(length);
+            }
+
+            if self
+                .fade
+                .as_ref()
+                .is_some_and(|fade| fade.position >= fade.length)
+            {
+                self.fade = None;
+            }
+        }
+
+        let mut sample = self
+            .current
+            .as_mut()
+            .and_then(|current| current.source.next());
+
+        if sample.is_none() && self.frame_pos == 0 && self.current.is_some() {
+            self.end_track();
+            sample = self
+                .current
+                .as_mut()
+                .and_then(|current| current.source.next());
+        }
+
+        let mut sample = sample.unwrap_or(0.0);
+
+        if let Some(fade) = &mut self.fade {
+            let (fade_out, fade_in) = fade.gains();
+            let outgoing = fade.source.next().unwrap_or(0.0);
+
+            sample = outgoing * fade_out + sample * fade_in;
+        }
+
+        self.frame_pos = (self.frame_pos + 1) % self.channels;
+
+        if self.frame_pos == 0 {
+            match &mut self.current {
+                Some(current) => {
+                    current.position += 1;
+                    self.position
+                        .set(current.position, current.length, self.sample_rate);
+                }
+                None => self.position.set(0, None, self.sample_rate),
+            }
+
+            if let Some(fade) = &mut self.fade {
+                fade.position += 1;
+            }
+        }
+
+        Some(sample)
+    }
+}
+
+impl Source for GaplessQueue {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Decodes the beginning of a track ahead of time, so starting it does not
+/// have to wait for the file to be read and the first packets decoded.
+pub struct Preroll {
+    inner: Track,
+    buffer: VecDeque<f32>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Preroll {
+    pub fn new(mut inner: Track) -> Preroll {
+        let channels = inner.channels();
+        let sample_rate = inner.sample_rate();
+
+        let samples = (PREROLL.as_secs_f32() * sample_rate as f32) as usize * channels as usize;
+        let buffer = inner.by_ref().take(samples).collect();
+
+        Preroll {
+            inner,
+            buffer,
+            channels,
+            sample_rate,
+        }
+    }
+}
+
+impl Iterator for Preroll {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.buffer.pop_front().or_else(|| self.inner.next())
+    }
+}
+
+impl Source for Preroll {
+    fn current_span_len(&self) -> Option<usize> {
+        if self.buffer.is_empty() {
+            self.inner.current_span_len()
+        } else {
+            Some(self.buffer.len())
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        if self.buffer.is_empty() {
+            self.inner.channels()
+        } else {
+            self.channels
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        if self.buffer.is_empty() {
+            self.inner.sample_rate()
+        } else {
+            self.sample_rate
+        }
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// Cuts encoder delay and padding off a track, counted in sample frames.
+pub struct Trim {
+    inner: Track,
+    delay: u64,
+    length: Option<u64>,
+    /// Samples left to drop before the first audible one.
+    skip: u64,
+    /// Samples left before the padding starts.
+    remaining: Option<u64>,
+}
+
+impl Trim {
+    pub fn new(inner: Track, delay: u64, length: Option<u64>) -> Trim {
+        let channels = inner.channels() as u64;
+
+        Trim {
+            inner,
+            delay,
+            length,
+            skip: delay * channels,
+            remaining: length.map(|length| length * channels),
+        }
+    }
+}
+
+impl Iterator for Trim {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.skip > 0 {
+            self.inner.next()?;
+            self.skip -= 1;
+        }
+
+        if let Some(remaining) = &mut self.remaining {
+            if *remaining == 0 {
+                return None;
+            }
+
+            *remaining -= 1;
+        }
+
+        self.inner.next()
+    }
+}
+
+impl Source for Trim {
+    fn current_span_len(&self) -> Option<usize> {
+        // Spans end early once the padding is reached.
+        match (self.inner.current_span_len(), self.remaining) {
+            (Some(len), Some(remaining)) if self.skip == 0 => Some(len.min(remaining as usize)),
+            (Some(len), None) if self.skip == 0 => Some(len),
+            _ => None,
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.length
+            .map(|length| Duration::from_secs_f64(length as f64 / self.sample_rate() as f64))
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let rate = self.sample_rate() as f64;
+        let channels = self.channels() as u64;
+        let delay = Duration::from_secs_f64(self.delay as f64 / rate);
+
+        self.inner.try_seek(pos + delay)?;
+
+        let frame = (pos.as_secs_f64() * rate) as u64;
+
+        self.skip = 0;
+        self.remaining = self
+            .length
+            .map(|length| length.saturating_sub(frame) * channels);
+
+        Ok(())
+    }
+}
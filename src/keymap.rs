@@ -0,0 +1,426 @@
+use color_eyre::eyre::eyre;
+use crossterm::event::KeyCode;
+use std::collections::HashMap;
+
+use KeyCode::{Backspace, Char, Enter};
+
+/// Where a binding applies. Global bindings work everywhere unless the
+/// current window binds the same key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    Global,
+    Browser,
+    Queue,
+    Playlists,
+    /// An open playlist.
+    Playlist,
+    Equalizer,
+    Devices,
+    Visualizer,
+    Log,
+}
+
+impl Context {
+    pub const ALL: [Context; 9] = [
+        Context::Global,
+        Context::Browser,
+        Context::Queue,
+        Context::Playlists,
+        Context::Playlist,
+        Context::Equalizer,
+        Context::Devices,
+        Context::Visualizer,
+        Context::Log,
+    ];
+
+    /// Name of the context's table in the `[keys]` section of the config.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Context::Global => "global",
+            Context::Browser => "browser",
+            Context::Queue => "queue",
+            Context::Playlists => "playlists",
+            Context::Playlist => "playlist",
+            Context::Equalizer => "equalizer",
+            Context::Devices => "devices",
+            Context::Visualizer => "visualizer",
+            Context::Log => "log",
+        }
+    }
+
+    pub fn get_title(&self) -> &'static str {
+        match self {
+            Context::Global => "Global",
+            Context::Browser => "Browser",
+            Context::Queue => "Queue",
+            Context::Playlists => "Playlists",
+            Context::Playlist => "Open playlist",
+            Context::Equalizer => "Equalizer",
+            Context::Devices => "Devices",
+            Context::Visualizer => "Visualizer",
+            Context::Log => "Log",
+        }
+    }
+}
+
+/// Every action with its description and default key. The windows handle
+/// the default keys, a remapped key is translated back before it gets
+/// there.
+const DEFAULTS: &[(Context, &str, &str, KeyCode)] = &[
+    (Context::Global, "quit", "Quit", Char('q')),
+    (Context::Global, "home", "Home window", Char('1')),
+    (Context::Global, "browser", "Browser window", Char('2')),
+    (Context::Global, "queue", "Queue window", Char('3')),
+    (Context::Global, "playlists", "Playlists window", Char('4')),
+    (Context::Global, "equalizer", "Equalizer window", Char('5')),
+    (Context::Global, "devices", "Devices window", Char('6')),
+    (
+        Context::Global,
+        "visualizer",
+        "Visualizer window",
+        Char('7'),
+    ),
+    (
+        Context::Global,
+        "now_playing",
+        "Now playing window",
+        Char('8'),
+    ),
+    (Context::Global, "log", "Log window", Char('9')),
+    (Context::Global, "play_pause", "Play or pause", Char('p')),
+    (Context::Global, "skip", "Next song", Char('s')),
+    (
+        Context::Global,
+        "stop",
+        "Stop and clear the queue",
+        Char('S'),
+    ),
+    (Context::Global, "speed_up", "Play faster", Char('+')),
+    (Context::Global, "speed_down", "Play slower", Char('-')),
+    (Context::Global, "reset_speed", "Normal speed", Char('0')),
+    (Context::Global, "command", "Enter a command", Char(':')),
+    (Context::Global, "help", "Show or hide this help", Char('?')),
+    (Context::Browser, "first", "First entry", Char('h')),
+    (Context::Browser, "next", "Next entry", Char('j')),
+    (Context::Browser, "previous", "Previous entry", Char('k')),
+    (Context::Browser, "last", "Last entry", Char('l')),
+    (
+        Context::Browser,
+        "open",
+        "Open directory or queue song",
+        Enter,
+    ),
+    (Context::Browser, "back", "Parent directory", Backspace),
+    (
+        Context::Browser,
+        "replace_queue",
+        "Replace the queue with a playlist",
+        Char('r'),
+    ),
+    (Context::Browser, "scan", "Measure loudness", Char('g')),
+    (
+        Context::Browser,
+        "scan_write",
+        "Measure loudness and write tags",
+        Char('G'),
+    ),
+    (Context::Queue, "first", "First song", Char('h')),
+    (Context::Queue, "next", "Next song", Char('j')),
+    (Context::Queue, "previous", "Previous song", Char('k')),
+    (Context::Queue, "last", "Last song", Char('l')),
+    (Context::Queue, "play", "Play from here", Enter),
+    (Context::Queue, "save_m3u", "Export as M3U", Char('w')),
+    (Context::Queue, "save_pls", "Export as PLS", Char('P')),
+    (Context::Queue, "save_xspf", "Export as XSPF", Char('X')),
+    (Context::Playlists, "first", "First playlist", Char('h')),
+    (Context::Playlists, "next", "Next playlist", Char('j')),
+    (
+        Context::Playlists,
+        "previous",
+        "Previous playlist",
+        Char('k'),
+    ),
+    (Context::Playlists, "last", "Last playlist", Char('l')),
+    (Context::Playlists, "open", "Show entries", Enter),
+    (Context::Playlists, "new", "New playlist", Char('n')),
+    (
+        Context::Playlists,
+        "new_smart",
+        "New smart playlist",
+        Char('N'),
+    ),
+    (Context::Playlists, "rename", "Rename", Char('R')),
+    (
+        Context::Playlists,
+        "edit_rule",
+        "Edit smart playlist rule",
+        Char('e'),
+    ),
+    (Context::Playlists, "delete", "Delete", Char('d')),
+    (Context::Playlists, "duplicate", "Duplicate", Char('y')),
+    (
+        Context::Playlists,
+        "add_current",
+        "Add the current song",
+        Char('a'),
+    ),
+    (
+        Context::Playlists,
+        "add_browser",
+        "Add the browser selection",
+        Char('A'),
+    ),
+    (
+        Context::Playlists,
+        "replace_queue",
+        "Replace the queue",
+        Char('r'),
+    ),
+    (Context::Playlist, "first", "First entry", Char('h')),
+    (Context::Playlist, "next", "Next entry", Char('j')),
+    (Context::Playlist, "previous", "Previous entry", Char('k')),
+    (Context::Playlist, "last", "Last entry", Char('l')),
+    (Context::Playlist, "move_down", "Move entry down", Char('J')),
+    (Context::Playlist, "move_up", "Move entry up", Char('K')),
+    (Context::Playlist, "remove", "Remove entry", Char('d')),
+    (
+        Context::Playlist,
+        "close",
+        "Back to the playlists",
+        Backspace,
+    ),
+    (
+        Context::Equalizer,
+        "previous_band",
+        "Previous band",
+        Char('h'),
+    ),
+    (Context::Equalizer, "next_band", "Next band", Char('l')),
+    (Context::Equalizer, "gain_up", "Raise gain", Char('k')),
+    (Context::Equalizer, "gain_down", "Lower gain", Char('j')),
+    (
+        Context::Equalizer,
+        "frequency_up",
+        "Raise frequency",
+        Char('K'),
+    ),
+    (
+        Context::Equalizer,
+        "frequency_down",
+        "Lower frequency",
+        Char('J'),
+    ),
+    (Context::Equalizer, "q_up", "Narrower band", Char(']')),
+    (Context::Equalizer, "q_down", "Wider band", Char('[')),
+    (Context::Equalizer, "toggle", "Turn on or off", Char('e')),
+    (Context::Equalizer, "next_preset", "Next preset", Char('n')),
+    (
+        Context::Equalizer,
+        "add_band",
+        "Add parametric band",
+        Char('a'),
+    ),
+    (
+        Context::Equalizer,
+        "delete_band",
+        "Delete parametric band",
+        Char('d'),
+    ),
+    (
+        Context::Equalizer,
+        "save",
+        "Save profile for this device",
+        Char('w'),
+    ),
+    (Context::Devices, "first", "First device", Char('h')),
+    (Context::Devices, "next", "Next device", Char('j')),
+    (Context::Devices, "previous", "Previous device", Char('k')),
+    (Context::Devices, "last", "Last device", Char('l')),
+    (Context::Devices, "select", "Play on this device", Enter),
+    (Context::Devices, "refresh", "Look for devices", Char('r')),
+    (
+        Context::Visualizer,
+        "mode",
+        "Spectrum or waveform",
+        Char('m'),
+    ),
+    (Context::Log, "first", "Oldest message", Char('h')),
+    (Context::Log, "next", "Next message", Char('j')),
+    (Context::Log, "previous", "Previous message", Char('k')),
+    (Context::Log, "last", "Newest message", Char('l')),
+    (Context::Log, "clear", "Clear the log", Char('c')),
+];
+
+pub struct Binding {
+    pub context: Context,
+    pub action: &'static str,
+    pub description: &'static str,
+    pub key: KeyCode,
+    /// The key the window handles.
+    default: KeyCode,
+}
+
+/// The active key bindings, the defaults with the user's changes from
+/// the `[keys]` section of the config applied.
+pub struct Keymap {
+    bindings: Vec<Binding>,
+}
+
+impl Keymap {
+    pub fn new(overrides: &HashMap<String, HashMap<String, String>>) -> color_eyre::Result<Keymap> {
+        let mut bindings: Vec<Binding> = DEFAULTS
+            .iter()
+            .map(|&(context, action, description, default)| Binding {
+                context,
+                action,
+                description,
+                key: default,
+                default,
+            })
+            .collect();
+
+        for (context_name, actions) in overrides {
+            let context = Context::ALL
+                .into_iter()
+                .find(|context| context.get_name() == context_name)
+                .ok_or_else(|| eyre!("Unknown key binding context \"{context_name}\"."))?;
+
+            for (action, key) in actions {
+                let binding = bindings
+                    .iter_mut()
+                    .find(|binding| binding.context == context && binding.action == action)
+                    .ok_or_else(|| eyre!("Unknown action \"{action}\" in keys.{context_name}."))?;
+
+                binding.key = parse_key(key).ok_or_else(|| {
+                    eyre!("Invalid key \"{key}\" for keys.{context_name}.{action}.")
+                })?;
+            }
+        }
+
+        // A key that does two things in one window would only ever do the
+        // first, so a conflict is reported instead of silently ignored.
+        for (i, binding) in bindings.iter().enumerate() {
+            if let Some(other) = bindings[..i]
+                .iter()
+                .find(|other| other.context == binding.context && other.key == binding.key)
+            {
+                return Err(eyre!(
+                    "Key \"{}\" is bound to both {} and {} in keys.{}.",
+                    key_name(binding.key),
+                    other.action,
+                    binding.action,
+                    binding.context.get_name()
+                ));
+            }
+        }
+
+        Ok(Keymap { bindings })
+    }
+
+    /// Finds what `key` does in `context`, returns the context of the
+    /// binding and the key its handler expects.
+    pub fn resolve(&self, context: Context, key: KeyCode) -> Option<(Context, KeyCode)> {
+        [context, Context::Global].into_iter().find_map(|context| {
+            self.bindings
+                .iter()
+                .find(|binding| binding.context == context && binding.key == key)
+                .map(|binding| (binding.context, binding.default))
+        })
+    }
+
+    pub fn get_bindings(&self, context: Context) -> impl Iterator<Item = &Binding> {
+        self.bindings
+            .iter()
+            .filter(move |binding| binding.context == context)
+    }
+
+    pub fn get_key(&self, context: Context, action: &str) -> Option<KeyCode> {
+        self.get_bindings(context)
+            .find(|binding| binding.action == action)
+            .map(|binding| binding.key)
+    }
+}
+
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("Enter", Enter),
+    ("Backspace", Backspace),
+    ("Esc", KeyCode::Esc),
+    ("Tab", KeyCode::Tab),
+    ("Space", Char(' ')),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Delete", KeyCode::Delete),
+];
+
+/// A single character or a key name like "Enter" or "F5".
+fn parse_key(key: &str) -> Option<KeyCode> {
+    let mut chars = key.chars();
+
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Char(c));
+    }
+
+    if let Some(&(_, code)) = NAMED_KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+    {
+        return Some(code);
+    }
+
+    key.strip_prefix(['F', 'f'])?.parse().ok().map(KeyCode::F)
+}
+
+pub fn key_name(key: KeyCode) -> String {
+    match key {
+        Char(' ') => String::from("Space"),
+        Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{n}"),
+        _ => NAMED_KEYS
+            .iter()
+            .find(|(_, code)| *code == key)
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| format!("{key:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(
+        context: &str,
+        action: &str,
+        key: &str,
+    ) -> HashMap<String, HashMap<String, String>> {
+        HashMap::from([(
+            context.to_string(),
+            HashMap::from([(action.to_string(), key.to_string())]),
+        )])
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        assert!(Keymap::new(&HashMap::new()).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_key_bound_twice_in_a_context() {
+        let error = Keymap::new(&overrides("browser", "next", "k"))
+            .err()
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            error,
+            "Key \"k\" is bound to both next and previous in keys.browser."
+        );
+
+        // Shadowing a global key in a window is fine.
+        assert!(Keymap::new(&overrides("browser", "next", "q")).is_ok());
+    }
+}
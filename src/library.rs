@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, TryRecvError},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{format, paths};
+
+const RESCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone)]
+pub struct TrackInfo {
+    path: PathBuf,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    genre: Option<String>,
+    year: Option<i32>,
+    track_number: Option<u16>,
+    added: Option<SystemTime>,
+    modified: Option<SystemTime>,
+}
+
+impl TrackInfo {
+    pub fn read(path: PathBuf) -> TrackInfo {
+        let metadata = fs::metadata(&path).ok();
+        let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+
+        // The creation time is not available on every filesystem.
+        let added = metadata
+            .as_ref()
+            .and_then(|m| m.created().ok())
+            .or(modified);
+
+        let mut track = TrackInfo {
+            path,
+            title: None,
+            artist: None,
+            album: None,
+            genre: None,
+            year: None,
+            track_number: None,
+            added,
+            modified,
+        };
+
+        if let Ok(tags) = format::read_tags(&track.path) {
+            track.title = tags.title;
+            track.artist = tags.artist;
+            track.album = tags.album;
+            track.genre = tags.genre;
+            track.year = tags.year;
+            track.track_number = tags.track_number;
+        }
+
+        track
+    }
+
+    pub fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn get_title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    pub fn get_artist(&self) -> Option<String> {
+        self.artist.clone()
+    }
+
+    pub fn get_album(&self) -> Option<String> {
+        self.album.clone()
+    }
+
+    pub fn get_genre(&self) -> Option<String> {
+        self.genre.clone()
+    }
+
+    pub fn get_year(&self) -> Option<i32> {
+        self.year
+    }
+
+    pub fn get_track_number(&self) -> Option<u16> {
+        self.track_number
+    }
+
+    pub fn get_added(&self) -> Option<SystemTime> {
+        self.added
+    }
+}
+
+/// Metadata index of every song below the music directory. A background
+/// thread rescans the directory and publishes a new index whenever files
+/// were added, removed or modified.
+pub struct Library {
+    tracks: Vec<TrackInfo>,
+    play_counts: HashMap<PathBuf, u32>,
+    generation: u64,
+    receiver: mpsc::Receiver<Vec<TrackInfo>>,
+}
+
+impl Library {
+    pub fn new(root: PathBuf) -> color_eyre::Result<Library> {
+        let (tx, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut known: HashMap<PathBuf, TrackInfo> = HashMap::new();
+            let mut first = true;
+
+            loop {
+                let mut files = vec![];
+                collect_songs(&root, &mut files);
+                files.sort();
+
+                let changed = first
+                    || files.len() != known.len()
+                    || files.iter().any(|(path, modified)| {
+                        known
+                            .get(path)
+                            .is_none_or(|track| track.modified != *modified)
+                    });
+
+                if changed {
+                    let tracks: Vec<TrackInfo> = files
+                        .into_iter()
+                        .map(|(path, modified)| match known.get(&path) {
+                            Some(track) if track.modified == modified => track.clone(),
+                            _ => TrackInfo::read(path),
+                        })
+                        .collect();
+
+                    known = tracks
+                        .iter()
+                        .map(|track| (track.path.clone(), track.clone()))
+                        .collect();
+
+                    if tx.send(tracks).is_err() {
+                        return;
+                    }
+                }
+
+                first = false;
+                thread::sleep(RESCAN_INTERVAL);
+            }
+        });
+
+        Ok(Library {
+            tracks: vec![],
+            play_counts: load_play_counts()?,
+            generation: 0,
+            receiver,
+        })
+    }
+
+    /// Picks up a finished rescan, returns true if the index changed.
+    pub fn check_for_update(&mut self) -> color_eyre::Result<bool> {
+        match self.receiver.try_recv() {
+            Ok(tracks) => {
+                self.tracks = tracks;
+                self.generation += 1;
+                Ok(true)
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => Ok(false),
+        }
+    }
+
+    pub fn record_play(&mut self, path: &Path) -> color_eyre::Result<()> {
+        *self.play_counts.entry(path.to_path_buf()).or_insert(0) += 1;
+        self.generation += 1;
+
+        save_play_counts(&self.play_counts)
+    }
+
+    pub fn get_tracks(&self) -> &[TrackInfo] {
+        &self.tracks
+    }
+
+    pub fn get_play_count(&self, path: &Path) -> u32 {
+        self.play_counts.get(path).copied().unwrap_or(0)
+    }
+
+    /// Incremented whenever the index or the play statistics change.
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+pub fn collect_songs(dir: &Path, files: &mut Vec<(PathBuf, Option<SystemTime>)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        if path.is_dir() {
+            collect_songs(&path, files);
+        } else if format::is_supported(&path) {
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+            files.push((path, modified));
+        }
+    }
+}
+
+fn play_counts_path() -> color_eyre::Result<PathBuf> {
+    let mut path = paths::data_dir()?;
+    path.push("playcounts.tsv");
+    Ok(path)
+}
+
+fn load_play_counts() -> color_eyre::Result<HashMap<PathBuf, u32>> {
+    let path = play_counts_path()?;
+
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| {
+            let (count, path) = line.split_once('\t')?;
+            Some((paths::unescape(path), count.parse().ok()?))
+        })
+        .collect())
+}
+
+fn save_play_counts(play_counts: &HashMap<PathBuf, u32>) -> color_eyre::Result<()> {
+    let mut writer = BufWriter::new(File::create(play_counts_path()?)?);
+
+    for (path, count) in play_counts {
+        writeln!(writer, "{count}\t{}", paths::escape(path))?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
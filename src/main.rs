@@ -1,22 +1,86 @@
 mod app;
 mod browser;
+mod command;
+mod config;
+mod cue;
+mod devices;
+mod eq;
+mod equalizer;
+mod format;
+mod gapless;
+mod keymap;
+mod library;
+mod notifications;
+mod now_playing;
+#[cfg(feature = "opus")]
+mod opus;
+mod output;
+mod overview;
+mod paths;
 mod player;
 mod player_controller;
 mod player_controller_message;
 mod player_message;
+mod playlist;
+mod playlists;
+mod query;
 mod queue;
+mod scanner;
+mod session;
 mod song;
+mod speed;
+mod terminal;
+mod theme;
 mod ui;
+mod visualizer;
 
-use crate::app::App;
+use std::{env, path::PathBuf};
+
+use crate::{
+    app::App,
+    scanner::{ScanEvent, ScanSummary},
+};
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
-    let app = App::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.first().is_some_and(|arg| arg == "scan") {
+        return scan(&args[1..]);
+    }
+
+    let mut app = App::new()?;
+
+    let terminal = terminal::init()?;
+    let result = app.run(terminal);
+    terminal::restore();
+
+    result
+}
+
+/// `scan [--write] <paths>...` measures loudness without starting the UI.
+fn scan(args: &[String]) -> color_eyre::Result<()> {
+    let write = args.iter().any(|arg| arg == "--write");
+    let paths: Vec<PathBuf> = args
+        .iter()
+        .filter(|arg| *arg != "--write")
+        .map(PathBuf::from)
+        .collect();
+
+    if paths.is_empty() {
+        eprintln!("Usage: tui-music-player scan [--write] <path>...");
+        return Ok(());
+    }
+
+    let summary: ScanSummary = scanner::scan(&paths, write, |event| match event {
+        ScanEvent::Scanning { index, total, path } => {
+            eprintln!("[{}/{}] {}", index + 1, total, path.display())
+        }
+        ScanEvent::Finished(_) | ScanEvent::Failed(_) => {}
+    })?;
+
+    eprintln!("{summary}");
 
-    let terminal = ratatui::init();
-    app?.run(terminal)?;
-    ratatui::restore();
     Ok(())
 }
@@ -0,0 +1,106 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use std::time::{Duration, Instant};
+
+/// How long a notification stays on screen.
+pub const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+/// Entries kept in the log, older ones are dropped.
+const LOG_LEN: usize = 200;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Error,
+}
+
+pub struct Notification {
+    pub level: Level,
+    pub message: String,
+    /// When it was last reported.
+    pub time: Instant,
+    /// How often it was reported in a row.
+    pub count: usize,
+}
+
+/// Errors and messages that don't end the program. The newest one is shown
+/// for a few seconds, all of them are kept in the log window.
+pub struct Notifications {
+    log: Vec<Notification>,
+    selected: usize,
+}
+
+impl Notifications {
+    pub fn new() -> Notifications {
+        Notifications {
+            log: vec![],
+            selected: 0,
+        }
+    }
+
+    pub fn handle_key_event(&mut self, key_event: KeyEvent) {
+        match key_event.code {
+            KeyCode::Char('h') => self.set_selected(0),
+            KeyCode::Char('j') => self.set_selected(self.selected + 1),
+            KeyCode::Char('k') => self.set_selected(self.selected.saturating_sub(1)),
+            KeyCode::Char('l') => self.set_selected(usize::MAX),
+            KeyCode::Char('c') => self.clear(),
+            _ => {}
+        }
+    }
+
+    pub fn info(&mut self, message: impl ToString) {
+        self.push(Level::Info, message.to_string());
+    }
+
+    pub fn error(&mut self, message: impl ToString) {
+        self.push(Level::Error, message.to_string());
+    }
+
+    /// A message reported again right away, e.g. on every frame, only
+    /// counts up instead of filling the log.
+    fn push(&mut self, level: Level, message: String) {
+        if let Some(last) = self.log.last_mut()
+            && last.level == level
+            && last.message == message
+        {
+            last.time = Instant::now();
+            last.count += 1;
+            return;
+        }
+
+        self.log.push(Notification {
+            level,
+            message,
+            time: Instant::now(),
+            count: 1,
+        });
+
+        let excess = self.log.len().saturating_sub(LOG_LEN);
+        self.log.drain(..excess);
+    }
+
+    /// The newest notification while it is still shown.
+    pub fn get_toast(&self) -> Option<&Notification> {
+        self.log
+            .last()
+            .filter(|notification| notification.time.elapsed() < TOAST_DURATION)
+    }
+
+    /// Every notification, oldest first.
+    pub fn get_log(&self) -> &[Notification] {
+        &self.log
+    }
+
+    pub fn get_selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn set_selected(&mut self, selected: usize) {
+        self.selected = selected.min(self.log.len().saturating_sub(1));
+    }
+
+    fn clear(&mut self) {
+        self.log.clear();
+        self.selected = 0;
+    }
+}
@@ -0,0 +1,134 @@
+use rodio::Source;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::mpsc::{self, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    format::{self, ReplayGain, Tags},
+    scanner,
+    song::Song,
+};
+
+/// Queue entries shown after the current song.
+pub const UP_NEXT: usize = 5;
+
+/// Details of a song that don't change while it plays.
+pub struct SongDetails {
+    pub tags: Tags,
+    pub format: Option<&'static str>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    /// Average of the whole file in kbit/s.
+    pub bitrate: Option<u32>,
+    pub replay_gain: ReplayGain,
+}
+
+/// State of the now playing window. The details are read in the
+/// background once the current song changes, not on every frame.
+pub struct NowPlaying {
+    /// Path and segment start of the song shown.
+    song: Option<(PathBuf, Option<Duration>)>,
+    /// Dropped when the song changes, a stale reader's result goes nowhere.
+    receiver: Option<mpsc::Receiver<SongDetails>>,
+    details: Option<SongDetails>,
+}
+
+impl NowPlaying {
+    pub fn new() -> NowPlaying {
+        NowPlaying {
+            song: None,
+            receiver: None,
+            details: None,
+        }
+    }
+
+    /// Starts reading the details of `song` unless they are already shown.
+    pub fn load(&mut self, song: Option<&Song>) {
+        let key = song.map(|song| (song.get_path(), song.get_start()));
+
+        if key == self.song {
+            return;
+        }
+
+        self.song = key;
+        self.details = None;
+        self.receiver = None;
+
+        let Some(song) = song.cloned() else {
+            return;
+        };
+
+        let (tx, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let _ = tx.send(SongDetails::read(&song));
+        });
+
+        self.receiver = Some(receiver);
+    }
+
+    /// Picks up details that finished reading.
+    /// Returns whether they became ready.
+    pub fn update(&mut self) -> bool {
+        let Some(receiver) = &self.receiver else {
+            return false;
+        };
+
+        match receiver.try_recv() {
+            Ok(details) => {
+                self.details = Some(details);
+                self.receiver = None;
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                self.receiver = None;
+                false
+            }
+        }
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.receiver.is_some()
+    }
+
+    pub fn get_details(&self) -> Option<&SongDetails> {
+        self.details.as_ref()
+    }
+}
+
+impl SongDetails {
+    fn read(song: &Song) -> SongDetails {
+        let path = song.get_path();
+        let source = format::open(&path).ok();
+
+        let bitrate = match (
+            fs::metadata(&path),
+            source.as_ref().and_then(|source| source.total_duration()),
+        ) {
+            (Ok(metadata), Some(duration)) if !duration.is_zero() => {
+                Some((metadata.len() as f64 * 8.0 / duration.as_secs_f64() / 1000.0).round() as u32)
+            }
+            _ => None,
+        };
+
+        // Files without tags may have been measured by the scanner.
+        let replay_gain = match format::read_replay_gain(&path) {
+            Ok(tags) if tags.track_gain.is_some() || tags.album_gain.is_some() => tags,
+            _ => scanner::cached_gain(&path).unwrap_or_default(),
+        };
+
+        SongDetails {
+            tags: format::read_tags(&path).unwrap_or_default(),
+            format: format::from_path(&path).map(|format| format.get_name()),
+            sample_rate: source.as_ref().map(|source| source.sample_rate()),
+            channels: source.as_ref().map(|source| source.channels()),
+            bitrate,
+            replay_gain,
+        }
+    }
+}
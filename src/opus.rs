@@ -0,0 +1,322 @@
+use audiopus::{coder::Decoder, Channels, SampleRate};
+use color_eyre::eyre::eyre;
+use ogg::{Packet, PacketReader};
+use rodio::{source::SeekError, Source};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, SeekFrom},
+    path::Path,
+    time::Duration,
+};
+
+/// Opus always decodes at 48 kHz.
+const OPUS_SAMPLE_RATE: u32 = 48_000;
+/// The longest Opus frame is 120 ms.
+const MAX_FRAME_SAMPLES: usize = 5760;
+/// Decoded and dropped before a seek target so the decoder has settled,
+/// 80 ms as RFC 7845 recommends.
+const SEEK_PREROLL: u64 = 3840;
+
+/// Decodes Ogg Opus files (RFC 7845) with libopus, honouring the pre-skip
+/// and the end trimming given by the final granule position.
+pub struct OpusDecoder {
+    reader: PacketReader<BufReader<File>>,
+    decoder: Decoder,
+    opus_channels: Channels,
+    channels: u16,
+    pre_skip: u64,
+    /// Samples per channel decoded so far, including the pre-skip.
+    position: u64,
+    /// Decoded samples before this position are dropped, the pre-skip or
+    /// the pre-roll of a seek.
+    skip_until: u64,
+    /// Packets read while finding out where a seek landed.
+    pending: VecDeque<Packet>,
+    buffer: Vec<f32>,
+    buffer_pos: usize,
+}
+
+impl OpusDecoder {
+    pub fn open(path: &Path) -> color_eyre::Result<OpusDecoder> {
+        let mut reader = PacketReader::new(BufReader::new(File::open(path)?));
+
+        let head = reader
+            .read_packet()?
+            .ok_or_else(|| eyre!("Empty Ogg stream: {}", path.display()))?;
+
+        if head.data.len() < 19 || !head.data.starts_with(b"OpusHead") {
+            return Err(eyre!("Not an Opus stream: {}", path.display()));
+        }
+
+        let channels = head.data[9];
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+
+        // Mapping family 0 covers mono and stereo, surround streams need the
+        // multistream decoder.
+        let opus_channels = match (channels, head.data[18]) {
+            (1, 0) => Channels::Mono,
+            (2, 0) => Channels::Stereo,
+            _ => return Err(eyre!("Unsupported Opus channel layout: {}", path.display())),
+        };
+
+        // OpusTags
+        reader.read_packet()?;
+
+        Ok(OpusDecoder {
+            reader,
+            decoder: Decoder::new(SampleRate::Hz48000, opus_channels)
+                .map_err(|e| eyre!("Failed to create Opus decoder. {e}"))?,
+            opus_channels,
+            channels: channels as u16,
+            pre_skip,
+            position: 0,
+            skip_until: pre_skip,
+            pending: VecDeque::new(),
+            buffer: vec![],
+            buffer_pos: 0,
+        })
+    }
+
+    fn decode_next_packet(&mut self) -> Option<()> {
+        let packet = match self.pending.pop_front() {
+            Some(packet) => packet,
+            None => self.reader.read_packet().ok()??,
+        };
+        let channels = self.channels as usize;
+
+        let mut output = vec![0.0f32; MAX_FRAME_SAMPLES * channels];
+
+        let mut samples = self
+            .decoder
+            .decode_float(Some(packet.data.as_slice()), output.as_mut_slice(), false)
+            .ok()?;
+
+        let start = self.position;
+        self.position += samples as u64;
+
+        // The granule position of the last page marks the real end of the
+        // stream, anything after that is encoder padding.
+        if packet.last_in_stream() && packet.absgp_page() < self.position {
+            samples = packet.absgp_page().saturating_sub(start) as usize;
+        }
+
+        let skip = self.skip_until.saturating_sub(start).min(samples as u64) as usize;
+
+        output.truncate(samples * channels);
+        self.buffer = output.split_off(skip * channels);
+        self.buffer_pos = 0;
+
+        Some(())
+    }
+
+    /// Moves to the first packet of the page holding `granule`, or to the
+    /// first one of the stream for 0.
+    fn seek_to(&mut self, granule: u64) -> io::Result<()> {
+        self.decoder =
+            Decoder::new(SampleRate::Hz48000, self.opus_channels).map_err(io::Error::other)?;
+        self.pending.clear();
+        self.buffer.clear();
+        self.buffer_pos = 0;
+
+        // The header pages have a granule position of 0 as well.
+        if granule == 0 {
+            self.reader.seek_bytes(SeekFrom::Start(0))?;
+
+            // OpusHead and OpusTags
+            for _ in 0..2 {
+                self.reader.read_packet().map_err(io::Error::other)?;
+            }
+
+            self.position = 0;
+            return Ok(());
+        }
+
+        if !self
+            .reader
+            .seek_absgp(None, granule)
+            .map_err(io::Error::other)?
+        {
+            // Past the end.
+            self.reader.seek_bytes(SeekFrom::End(0))?;
+            return Ok(());
+        }
+
+        // Only pages have a granule position, the one of the first packet
+        // is that of the page minus the samples in its packets.
+        let mut samples = 0;
+
+        while let Some(packet) = self.reader.read_packet().map_err(io::Error::other)? {
+            samples += audiopus::packet::nb_samples(&packet.data, SampleRate::Hz48000)
+                .map_err(io::Error::other)? as u64;
+
+            let last_in_page = packet.last_in_page();
+            let last_in_stream = packet.last_in_stream();
+            let end = packet.absgp_page();
+            self.pending.push_back(packet);
+
+            // The granule position of the last page is trimmed to the end of
+            // the audio, it doesn't tell where the page starts.
+            if last_in_stream {
+                return self.seek_to(0);
+            }
+
+            if last_in_page {
+                self.position = end.saturating_sub(samples);
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for OpusDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        while self.buffer_pos >= self.buffer.len() {
+            self.decode_next_packet()?;
+        }
+
+        let sample = self.buffer[self.buffer_pos];
+        self.buffer_pos += 1;
+
+        Some(sample)
+    }
+}
+
+impl Source for OpusDecoder {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        OPUS_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let target =
+            self.pre_skip + (pos.as_secs_f64() * f64::from(OPUS_SAMPLE_RATE)).round() as u64;
+
+        self.seek_to(target.saturating_sub(SEEK_PREROLL))
+            .map_err(|e| SeekError::Other(Box::new(e)))?;
+        self.skip_until = target;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audiopus::{coder::Encoder, Application};
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+    use std::{env, f32::consts::TAU, fs};
+
+    const SECONDS: usize = 5;
+    const FRAME: usize = 960;
+    /// 200 ms
+    const SETTLED: usize = 9600;
+
+    /// Writes a stereo sine as Ogg Opus, with pages of 1.2 seconds.
+    fn encode(path: &Path) -> u64 {
+        let encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio).unwrap();
+        let pre_skip = encoder.lookahead().unwrap() as u64;
+        let total = (SECONDS * OPUS_SAMPLE_RATE as usize) as u64;
+
+        let mut file = vec![];
+        let mut writer = PacketWriter::new(&mut file);
+
+        let mut head = b"OpusHead\x01\x02".to_vec();
+        head.extend((pre_skip as u16).to_le_bytes());
+        head.extend(OPUS_SAMPLE_RATE.to_le_bytes());
+        head.extend([0, 0, 0]);
+        writer
+            .write_packet(head, 1, PacketWriteEndInfo::EndPage, 0)
+            .unwrap();
+        writer
+            .write_packet(
+                b"OpusTags\0\0\0\0\0\0\0\0".to_vec(),
+                1,
+                PacketWriteEndInfo::EndPage,
+                0,
+            )
+            .unwrap();
+
+        let frames = (total + pre_skip).div_ceil(FRAME as u64);
+
+        for frame in 0..frames {
+            let input: Vec<f32> = (0..FRAME * 2)
+                .map(|i| {
+                    let sample = frame as usize * FRAME + i / 2;
+                    (sample as f32 * TAU * 440.0 / OPUS_SAMPLE_RATE as f32).sin() * 0.5
+                })
+                .collect();
+
+            let mut output = vec![0; 4000];
+            let len = encoder.encode_float(&input, &mut output).unwrap();
+            output.truncate(len);
+
+            let (end, granule) = if frame + 1 == frames {
+                (PacketWriteEndInfo::EndStream, total + pre_skip)
+            } else if (frame + 1) % 60 == 0 {
+                (PacketWriteEndInfo::EndPage, (frame + 1) * FRAME as u64)
+            } else {
+                (PacketWriteEndInfo::NormalPacket, (frame + 1) * FRAME as u64)
+            };
+
+            writer.write_packet(output, 1, end, granule).unwrap();
+        }
+
+        drop(writer);
+        fs::write(path, file).unwrap();
+
+        total
+    }
+
+    #[test]
+    fn seeks_to_the_sample() {
+        let path = env::temp_dir().join(format!("opus-seek-{}.opus", std::process::id()));
+        let total = encode(&path) as usize;
+
+        let full: Vec<f32> = OpusDecoder::open(&path).unwrap().collect();
+        assert_eq!(full.len(), total * 2);
+
+        // The middle of a page, the last page, which is trimmed, and the
+        // start where the pre-roll reaches back to the headers.
+        for seconds in [2.5, 4.9, 0.05] {
+            let mut decoder = OpusDecoder::open(&path).unwrap();
+            decoder.try_seek(Duration::from_secs_f64(seconds)).unwrap();
+            let seeked: Vec<f32> = decoder.collect();
+
+            let start = (seconds * OPUS_SAMPLE_RATE as f64) as usize * 2;
+            assert_eq!(seeked.len(), full.len() - start, "seek to {seconds}s");
+
+            // The decoder state differs from a full decode at first, until
+            // it has settled the samples match.
+            let error = seeked
+                .iter()
+                .zip(&full[start..])
+                .skip(SETTLED * 2)
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f32::max);
+            assert!(error < 0.001, "seek to {seconds}s is off by {error}");
+        }
+
+        let mut decoder = OpusDecoder::open(&path).unwrap();
+        decoder.try_seek(Duration::from_secs(60)).unwrap();
+        assert_eq!(decoder.count(), 0);
+
+        fs::remove_file(path).unwrap();
+    }
+}
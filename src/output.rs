@@ -0,0 +1,702 @@
+use color_eyre::eyre::eyre;
+use rodio::{
+    cpal::{
+        self,
+        traits::{DeviceTrait, HostTrait},
+    },
+    OutputStream, OutputStreamBuilder, Source,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{OutputBackend, OutputConfig},
+    visualizer::Tap,
+};
+
+/// Samples moved from the pipeline to the output at once.
+const CHUNK_SAMPLES: usize = 1024;
+
+/// Format the pipeline runs at when it isn't started on a device.
+const DEFAULT_CHANNELS: u16 = 2;
+const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// How much audio the null and WAV outputs take per step.
+const WRITE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The sizes in a WAV header are 32 bits, the data has to end before the
+/// file reaches 4 GiB.
+const MAX_WAV_DATA_BYTES: u32 = u32::MAX - 36;
+
+/// Names of the output devices currently available.
+pub fn list_devices() -> Vec<String> {
+    cpal::default_host()
+        .output_devices()
+        .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+        .unwrap_or_default()
+}
+
+pub fn default_device_name() -> String {
+    cpal::default_host()
+        .default_output_device()
+        .and_then(|device| device.name().ok())
+        .unwrap_or_else(|| String::from("default"))
+}
+
+/// The player's whole signal chain. It lives independently of any output,
+/// so switching devices continues exactly where the old one stopped.
+#[derive(Clone)]
+pub struct Pipeline {
+    source: Arc<Mutex<Box<dyn Source + Send>>>,
+    paused: Arc<AtomicBool>,
+    /// Linear gain applied last, stored as the bits of an f32.
+    volume: Arc<AtomicU32>,
+    /// Gets a copy of everything sent to the output, for the visualizer.
+    tap: Tap,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Pipeline {
+    pub fn new(source: impl Source + Send + 'static, tap: Tap) -> Pipeline {
+        Pipeline {
+            channels: source.channels(),
+            sample_rate: source.sample_rate(),
+            source: Arc::new(Mutex::new(Box::new(source))),
+            paused: Arc::new(AtomicBool::new(false)),
+            volume: Arc::new(AtomicU32::new(1f32.to_bits())),
+            tap,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// While paused the output plays silence and the pipeline stands still.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+    }
+
+    /// Sets the volume between 0 and 1.
+    pub fn set_volume(&self, volume: f32) {
+        self.volume
+            .store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    fn source(&self) -> PipelineSource {
+        PipelineSource {
+            pipeline: self.clone(),
+            buffer: vec![],
+            position: 0,
+        }
+    }
+}
+
+/// Reads the pipeline in chunks, so the lock isn't taken for every sample.
+/// Never ends, silence is played when the pipeline runs dry.
+struct PipelineSource {
+    pipeline: Pipeline,
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Iterator for PipelineSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.buffer.len() {
+            let chunk = CHUNK_SAMPLES / self.pipeline.channels.max(1) as usize
+                * self.pipeline.channels.max(1) as usize;
+
+            self.buffer.clear();
+            self.position = 0;
+
+            if !self.pipeline.is_paused()
+                && let Ok(mut source) = self.pipeline.source.lock()
+            {
+                self.buffer.extend(source.by_ref().take(chunk));
+            }
+
+            self.buffer.resize(chunk, 0.0);
+
+            self.pipeline.tap.push(
+                &self.buffer,
+                self.pipeline.channels,
+                self.pipeline.sample_rate,
+            );
+
+            // The visualizer shows the signal whatever the volume.
+            let volume = f32::from_bits(self.pipeline.volume.load(Ordering::Relaxed));
+
+            if volume != 1.0 {
+                self.buffer.iter_mut().for_each(|sample| *sample *= volume);
+            }
+        }
+
+        let sample = self.buffer[self.position];
+        self.position += 1;
+
+        Some(sample)
+    }
+}
+
+impl Source for PipelineSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.pipeline.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.pipeline.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+enum Backend {
+    Device(OutputStream),
+    /// Null and WAV outputs, the writer starts once there is something to
+    /// play.
+    File {
+        wav: Option<WavWriter>,
+        writer: Option<Writer>,
+        on_error: Arc<dyn Fn(String) + Send + Sync>,
+    },
+}
+
+/// Where the player's audio goes: a sound card, nowhere, or a WAV file.
+/// Null and WAV outputs consume the pipeline in real time, so queue
+/// advancement and callbacks behave as they do on a device.
+pub struct Output {
+    backend: Backend,
+    device: String,
+}
+
+impl Output {
+    /// Opens the output chosen in `config`. A device that isn't connected
+    /// falls back to the system default. `on_lost` is called once if the
+    /// device fails later on, e.g. because it was unplugged, `on_error`
+    /// when writing a file fails.
+    pub fn open(
+        config: &OutputConfig,
+        on_lost: impl Fn() + Clone + Send + 'static,
+        on_error: impl Fn(String) + Send + Sync + 'static,
+    ) -> color_eyre::Result<Output> {
+        match config.backend {
+            OutputBackend::Device => Output::open_device(config.device.as_deref(), on_lost),
+            OutputBackend::Null => Ok(Output {
+                backend: Backend::File {
+                    wav: None,
+                    writer: None,
+                    on_error: Arc::new(on_error),
+                },
+                device: String::from("null"),
+            }),
+            OutputBackend::Wav => {
+                let path = config
+                    .path
+                    .as_deref()
+                    .ok_or_else(|| eyre!("No file set for the WAV output."))?;
+
+                Ok(Output {
+                    backend: Backend::File {
+                        wav: Some(WavWriter::create(path)?),
+                        writer: None,
+                        on_error: Arc::new(on_error),
+                    },
+                    device: format!("wav:{}", path.display()),
+                })
+            }
+        }
+    }
+
+    fn open_device(
+        device: Option<&str>,
+        on_lost: impl Fn() + Clone + Send + 'static,
+    ) -> color_eyre::Result<Output> {
+        let host = cpal::default_host();
+
+        let device = device
+            .and_then(|name| {
+                host.output_devices()
+                    .ok()?
+                    .find(|device| device.name().is_ok_and(|n| n == name))
+            })
+            .or_else(|| host.default_output_device())
+            .ok_or_else(|| eyre!("No audio output device available."))?;
+
+        let name = device.name().unwrap_or_else(|_| String::from("default"));
+        let lost = Arc::new(AtomicBool::new(false));
+
+        let mut stream = OutputStreamBuilder::from_device(device)?
+            .with_error_callback(move |_| {
+                if !lost.swap(true, Ordering::SeqCst) {
+                    on_lost();
+                }
+            })
+            .open_stream_or_fallback()?;
+
+        // Closing a stream is expected here, don't print over the UI.
+        stream.log_on_drop(false);
+
+        Ok(Output {
+            backend: Backend::Device(stream),
+            device: name,
+        })
+    }
+
+    pub fn get_device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn get_channels(&self) -> u16 {
+        match &self.backend {
+            Backend::Device(stream) => stream.config().channel_count(),
+            Backend::File { .. } => DEFAULT_CHANNELS,
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        match &self.backend {
+            Backend::Device(stream) => stream.config().sample_rate(),
+            Backend::File { .. } => DEFAULT_SAMPLE_RATE,
+        }
+    }
+
+    /// Starts playing `pipeline`. A device's mixer converts it to the
+    /// device's format if needed, files are written in the pipeline's
+    /// format.
+    pub fn play(&mut self, pipeline: &Pipeline) {
+        match &mut self.backend {
+            Backend::Device(stream) => stream.mixer().add(pipeline.source()),
+            Backend::File {
+                wav,
+                writer,
+                on_error,
+            } => {
+                let mut wav = wav.take();
+                let on_error = on_error.clone();
+
+                if let Some(wav) = &mut wav {
+                    // Errors show up again when writing the samples.
+                    let _ = wav.write_header(pipeline.channels, pipeline.sample_rate);
+                }
+
+                *writer = Some(Writer::start(
+                    pipeline.source(),
+                    move |samples| match &mut wav {
+                        Some(wav) => wav.write(samples),
+                        None => Ok(()),
+                    },
+                    move |e| on_error(format!("Stopped writing the WAV file. {e}")),
+                ));
+            }
+        }
+    }
+}
+
+/// Pulls samples from the pipeline at the speed they would be played and
+/// hands them to `write`. Stops when dropped, or when writing fails after
+/// passing the error to `on_error`.
+struct Writer {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Writer {
+    fn start(
+        mut source: PipelineSource,
+        mut write: impl FnMut(&[f32]) -> io::Result<()> + Send + 'static,
+        on_error: impl FnOnce(io::Error) + Send + 'static,
+    ) -> Writer {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let thread = thread::spawn(move || {
+            let frames =
+                (source.sample_rate() as f32 * WRITE_INTERVAL.as_secs_f32()).max(1.0) as usize;
+            let samples = frames * source.channels() as usize;
+
+            let mut buffer = Vec::with_capacity(samples);
+            let mut deadline = Instant::now();
+
+            while !thread_stop.load(Ordering::SeqCst) {
+                buffer.clear();
+                buffer.extend(source.by_ref().take(samples));
+
+                if let Err(e) = write(&buffer) {
+                    on_error(e);
+                    return;
+                }
+
+                // Sleep towards a fixed schedule so the timing doesn't drift.
+                deadline += WRITE_INTERVAL;
+                thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            }
+        });
+
+        Writer {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A 32-bit float WAV file. The sizes in the header are filled in when
+/// the writer is dropped.
+struct WavWriter {
+    file: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path) -> color_eyre::Result<WavWriter> {
+        Ok(WavWriter {
+            file: BufWriter::new(File::create(path)?),
+            data_bytes: 0,
+        })
+    }
+
+    fn write_header(&mut self, channels: u16, sample_rate: u32) -> io::Result<()> {
+        const FORMAT_IEEE_FLOAT: u16 = 3;
+        let block_align = channels * 4;
+
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&36u32.to_le_bytes())?;
+        self.file.write_all(b"WAVEfmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&FORMAT_IEEE_FLOAT.to_le_bytes())?;
+        self.file.write_all(&channels.to_le_bytes())?;
+        self.file.write_all(&sample_rate.to_le_bytes())?;
+        self.file
+            .write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        self.file.write_all(&block_align.to_le_bytes())?;
+        self.file.write_all(&32u16.to_le_bytes())?;
+        self.file.write_all(b"data")?;
+        self.file.write_all(&0u32.to_le_bytes())
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_bytes = u32::try_from(samples.len() * 4)
+            .ok()
+            .and_then(|bytes| self.data_bytes.checked_add(bytes))
+            .filter(|bytes| *bytes <= MAX_WAV_DATA_BYTES)
+            .ok_or_else(|| io::Error::other("It reached the 4 GiB limit of the format."))?;
+
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+
+        self.data_bytes = data_bytes;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + self.data_bytes).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_bytes.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{PlaybackConfig, ReplayGainMode},
+        eq::EqHandle,
+        format,
+        gapless::TrackPosition,
+        player::{Player, PlayerCommand},
+        player_controller_message::{ControllerCommand, ControllerSender},
+        song::Song,
+    };
+    use std::{env, fs, path::PathBuf, sync::mpsc};
+
+    /// Half a second of stereo audio at the pipeline's rate.
+    const FRAMES: usize = 22_050;
+
+    fn crc8(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0, |mut crc, byte| {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    crc << 1 ^ 0x07
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    fn crc16(bytes: &[u8]) -> u16 {
+        bytes.iter().fold(0, |mut crc, byte| {
+            crc ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    crc << 1 ^ 0x8005
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+
+    /// A 16-bit stereo FLAC file at 44.1 kHz with uncompressed frames, every
+    /// sample of it different.
+    fn write_flac(path: &Path, track: i16, comments: &[&str]) {
+        const BLOCK: usize = 4096;
+
+        let mut file = b"fLaC".to_vec();
+
+        file.extend([0, 0, 0, 34]);
+        file.extend((BLOCK as u16).to_be_bytes());
+        file.extend((BLOCK as u16).to_be_bytes());
+        file.extend([0; 6]);
+        file.extend((44_100u64 << 44 | 1 << 41 | 15 << 36 | FRAMES as u64).to_be_bytes());
+        file.extend([0; 16]);
+
+        let mut vorbis_comment = vec![0, 0, 0, 0];
+        vorbis_comment.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            vorbis_comment.extend((comment.len() as u32).to_le_bytes());
+            vorbis_comment.extend(comment.as_bytes());
+        }
+        file.push(0x80 | 4);
+        file.extend(&(vorbis_comment.len() as u32).to_be_bytes()[1..]);
+        file.extend(vorbis_comment);
+
+        for (number, start) in (0..FRAMES).step_by(BLOCK).enumerate() {
+            let len = BLOCK.min(FRAMES - start);
+
+            let mut frame = vec![0xff, 0xf8, if len == BLOCK { 0xc9 } else { 0x79 }, 0x18];
+            frame.push(number as u8);
+            if len != BLOCK {
+                frame.extend((len as u16 - 1).to_be_bytes());
+            }
+            frame.push(crc8(&frame));
+
+            for channel in 0..2 {
+                // Verbatim subframe
+                frame.push(0x02);
+                for i in start..start + len {
+                    let sample = (i as i16 + 1 + track) * if channel == 0 { 1 } else { -1 };
+                    frame.extend(sample.to_be_bytes());
+                }
+            }
+
+            frame.extend(crc16(&frame).to_be_bytes());
+            file.extend(frame);
+        }
+
+        fs::write(path, file).unwrap();
+    }
+
+    fn read_wav(path: &Path) -> Vec<f32> {
+        let file = fs::read(path).unwrap();
+        let size = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap()) as usize;
+
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(size(4), file.len() - 8);
+        assert_eq!(size(40), file.len() - 44);
+
+        file[44..]
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect()
+    }
+
+    fn decode(path: &Path) -> Vec<f32> {
+        format::open(path).unwrap().collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Runs a player on `backend` through `songs`, doing the player thread's
+    /// part. `on_started` is called with the player whenever a track starts.
+    /// Returns the ReplayGain of each track that started, and the number of
+    /// tracks that ended.
+    fn play(
+        output: OutputConfig,
+        config: PlaybackConfig,
+        songs: &[Song],
+        mut on_started: impl FnMut(&Player, usize),
+    ) -> (Vec<Option<f32>>, usize) {
+        let (tx, rx) = mpsc::channel();
+        let (player_tx, player_rx) = mpsc::channel();
+
+        let mut player = Player::new(
+            ControllerSender::new(tx, None),
+            player_tx,
+            config,
+            EqHandle::default(),
+            Tap::new(),
+            TrackPosition::default(),
+        )
+        .unwrap();
+
+        player.start(output).unwrap();
+
+        for song in songs {
+            player.add_to_queue(song.clone()).unwrap();
+        }
+
+        let mut started = vec![];
+        let mut ended = 0;
+
+        while ended < songs.len() {
+            if let Ok(message) = player_rx.recv_timeout(Duration::from_millis(10))
+                && let PlayerCommand::Preload = message.get_command()
+            {
+                player.preload();
+            }
+
+            for message in rx.try_iter() {
+                match message.get_command() {
+                    ControllerCommand::TrackStarted => {
+                        started.push(message.get_gain());
+                        on_started(&player, started.len());
+                    }
+                    ControllerCommand::PopQueue => ended += 1,
+                    ControllerCommand::Error => panic!("{:?}", message.get_error()),
+                    _ => {}
+                }
+            }
+        }
+
+        // The output lags a little behind the queue, by the samples buffered
+        // in the pipeline.
+        thread::sleep(Duration::from_millis(200));
+
+        (started, ended)
+    }
+
+    #[test]
+    fn wav_output_is_sample_exact() {
+        let dir = temp_dir("wav-output");
+        let one = dir.join("one.flac");
+        let two = dir.join("two.flac");
+        let wav = dir.join("out.wav");
+
+        write_flac(&one, 0, &["REPLAYGAIN_TRACK_GAIN=-6.00 dB"]);
+        write_flac(&two, 10_000, &[]);
+
+        let seek = Duration::from_millis(300);
+
+        let (started, ended) = play(
+            OutputConfig {
+                backend: OutputBackend::Wav,
+                device: None,
+                path: Some(wav.clone()),
+            },
+            PlaybackConfig {
+                replay_gain: ReplayGainMode::Track,
+                ..PlaybackConfig::default()
+            },
+            &[Song::new(one.clone()), Song::new(two.clone())],
+            |player, track| {
+                if track == 1 {
+                    player.seek(seek).unwrap();
+                }
+            },
+        );
+
+        assert_eq!(started, [Some(-6.0), None]);
+        assert_eq!(ended, 2);
+
+        let gain = 10f32.powf(-6.0 / 20.0);
+        let one: Vec<f32> = decode(&one).iter().map(|sample| sample * gain).collect();
+        let two = decode(&two);
+        let output = read_wav(&wav);
+
+        // Silence until the first track starts, then the first track up to
+        // wherever the seek caught it.
+        let start = output.iter().position(|sample| *sample != 0.0).unwrap();
+        assert_eq!(start % 2, 0);
+        let output = &output[start..];
+
+        let played = output.iter().zip(&one).take_while(|(a, b)| a == b).count();
+        assert_eq!(played % 2, 0);
+
+        // The rest of the first track from the seek position, the second
+        // track right after it and silence once the queue ran dry.
+        let target = seek.as_millis() as usize * 44_100 / 1000 * 2;
+        let expected: Vec<f32> = one[target..].iter().chain(&two).copied().collect();
+        let output = &output[played..];
+
+        assert!(played < one.len());
+        assert!(output.len() >= expected.len());
+        assert_eq!(
+            output.iter().zip(&expected).position(|(a, b)| a != b),
+            None,
+            "first sample that differs"
+        );
+        assert!(output[expected.len()..].iter().all(|sample| *sample == 0.0));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn null_output_plays_in_real_time() {
+        let dir = temp_dir("null-output");
+        let path = dir.join("one.flac");
+        write_flac(&path, 0, &[]);
+
+        let mut started_at = None;
+        let (started, ended) = play(
+            OutputConfig {
+                backend: OutputBackend::Null,
+                device: None,
+                path: None,
+            },
+            PlaybackConfig::default(),
+            &[Song::new(path)],
+            |_, _| started_at = Some(Instant::now()),
+        );
+
+        assert_eq!(started, [None]);
+        assert_eq!(ended, 1);
+        assert!(started_at.unwrap().elapsed() >= Duration::from_millis(400));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
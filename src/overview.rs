@@ -0,0 +1,271 @@
+use std::{
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, TryRecvError},
+        Arc,
+    },
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{paths, song::Song};
+
+/// Points of every overview, whatever the length of the track.
+pub const POINTS: usize = 512;
+
+/// Sample frames summarised before the track length is known.
+const BLOCK_FRAMES: usize = 1024;
+
+/// Cached overviews kept, the oldest ones are removed beyond that.
+const MAX_CACHED: usize = 2000;
+
+/// Loudness of one slice of a track, both between 0 and 1.
+#[derive(Clone, Copy, Default)]
+pub struct OverviewPoint {
+    pub rms: f32,
+    pub peak: f32,
+}
+
+/// The waveform overview of the current track. Overviews are computed by
+/// one background worker and cached on disk, so each file is only decoded
+/// once.
+pub struct Overview {
+    /// Path and segment start of the song shown, or being computed.
+    song: Option<(PathBuf, Option<Duration>)>,
+    /// Counts up with every song, the worker drops a song that is no
+    /// longer the newest.
+    generation: Arc<AtomicUsize>,
+    requests: mpsc::Sender<(usize, Song)>,
+    results: mpsc::Receiver<(usize, Vec<OverviewPoint>)>,
+    points: Vec<OverviewPoint>,
+}
+
+impl Overview {
+    pub fn new() -> Overview {
+        let generation = Arc::new(AtomicUsize::new(0));
+        let (requests, worker_requests) = mpsc::channel();
+        let (worker_results, results) = mpsc::channel();
+
+        let current = generation.clone();
+
+        // Ends once the overview is dropped and the requests are closed.
+        thread::spawn(move || {
+            while let Ok(mut request) = worker_requests.recv() {
+                // Only the newest of the songs that piled up matters.
+                while let Ok(newer) = worker_requests.try_recv() {
+                    request = newer;
+                }
+
+                let (generation, song) = request;
+                let is_current = || current.load(Ordering::Relaxed) == generation;
+
+                if !is_current() {
+                    continue;
+                }
+
+                if let Some(points) = cached(&song).or_else(|| compute(&song, &is_current))
+                    && worker_results.send((generation, points)).is_err()
+                {
+                    return;
+                }
+            }
+        });
+
+        Overview {
+            song: None,
+            generation,
+            requests,
+            results,
+            points: vec![],
+        }
+    }
+
+    /// Starts computing the overview of `song` unless it is already shown.
+    pub fn load(&mut self, song: Option<&Song>) {
+        let key = song.map(|song| (song.get_path(), song.get_start()));
+
+        if key == self.song {
+            return;
+        }
+
+        self.song = key;
+        self.points.clear();
+
+        // Cancels the song being computed.
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(song) = song {
+            let _ = self.requests.send((generation, song.clone()));
+        }
+    }
+
+    /// Picks up an overview that finished computing.
+    /// Returns whether the overview became ready.
+    pub fn update(&mut self) -> bool {
+        let mut ready = false;
+
+        loop {
+            match self.results.try_recv() {
+                Ok((generation, points))
+                    if generation == self.generation.load(Ordering::Relaxed) =>
+                {
+                    self.points = points;
+                    ready = true;
+                }
+                Ok(_) => {}
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return ready,
+            }
+        }
+    }
+
+    /// Empty until the overview is ready.
+    pub fn get_points(&self) -> &[OverviewPoint] {
+        &self.points
+    }
+}
+
+/// Decodes the whole song and reduces it to [`POINTS`] points. Stops early
+/// with None once `is_current` turns false.
+fn compute(song: &Song, is_current: &impl Fn() -> bool) -> Option<Vec<OverviewPoint>> {
+    let mut source = song.open(Duration::ZERO).ok()?;
+    let channels = source.channels().max(1) as usize;
+
+    // Mean square and peak of each block.
+    let mut blocks: Vec<(f32, f32)> = vec![];
+    let mut buffer = Vec::with_capacity(BLOCK_FRAMES * channels);
+
+    loop {
+        buffer.clear();
+        buffer.extend(source.by_ref().take(BLOCK_FRAMES * channels));
+
+        if buffer.is_empty() {
+            break;
+        }
+
+        if !is_current() {
+            return None;
+        }
+
+        let square = buffer.iter().map(|sample| sample * sample).sum::<f32>() / buffer.len() as f32;
+        let peak = buffer
+            .iter()
+            .fold(0.0, |peak: f32, sample| peak.max(sample.abs()));
+
+        blocks.push((square, peak));
+    }
+
+    if blocks.is_empty() {
+        return None;
+    }
+
+    let points: Vec<OverviewPoint> = (0..POINTS)
+        .map(|i| {
+            let first = i * blocks.len() / POINTS;
+            let last = ((i + 1) * blocks.len() / POINTS).max(first + 1);
+            let slice = &blocks[first..last];
+
+            OverviewPoint {
+                rms: (slice.iter().map(|(square, _)| square).sum::<f32>() / slice.len() as f32)
+                    .sqrt()
+                    .min(1.0),
+                peak: slice
+                    .iter()
+                    .map(|(_, peak)| *peak)
+                    .fold(0.0, f32::max)
+                    .min(1.0),
+            }
+        })
+        .collect();
+
+    let _ = save(song, &points);
+
+    Some(points)
+}
+
+fn cache_dir() -> color_eyre::Result<PathBuf> {
+    let mut dir = paths::cache_dir()?;
+    dir.push("overviews");
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
+
+/// Cache file of `song`, changes whenever the file is modified. The name
+/// is an FNV-1a hash, which unlike the std hashers stays the same across
+/// Rust releases.
+fn cache_path(song: &Song) -> color_eyre::Result<PathBuf> {
+    let path = song.get_path();
+    let modified = fs::metadata(&path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let nanos = |time: Option<Duration>| time.map_or(u128::MAX, |time| time.as_nanos());
+
+    let hash = [
+        path.as_os_str().as_bytes(),
+        &modified.as_nanos().to_le_bytes(),
+        &nanos(song.get_start()).to_le_bytes(),
+        &nanos(song.get_end()).to_le_bytes(),
+    ]
+    .concat()
+    .iter()
+    .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    Ok(cache_dir()?.join(format!("{hash:016x}")))
+}
+
+/// Removes the oldest overviews beyond [`MAX_CACHED`].
+fn prune_cache() -> color_eyre::Result<()> {
+    let mut files: Vec<_> = fs::read_dir(cache_dir()?)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+
+    if files.len() <= MAX_CACHED {
+        return Ok(());
+    }
+
+    files.sort();
+
+    for (_, path) in &files[..files.len() - MAX_CACHED] {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Stored as one byte for the RMS and one for the peak of every point.
+fn cached(song: &Song) -> Option<Vec<OverviewPoint>> {
+    let bytes = fs::read(cache_path(song).ok()?).ok()?;
+
+    if bytes.len() != POINTS * 2 {
+        return None;
+    }
+
+    Some(
+        bytes
+            .chunks_exact(2)
+            .map(|point| OverviewPoint {
+                rms: point[0] as f32 / 255.0,
+                peak: point[1] as f32 / 255.0,
+            })
+            .collect(),
+    )
+}
+
+fn save(song: &Song, points: &[OverviewPoint]) -> color_eyre::Result<()> {
+    let bytes: Vec<u8> = points
+        .iter()
+        .flat_map(|point| [(point.rms * 255.0) as u8, (point.peak * 255.0) as u8])
+        .collect();
+
+    fs::write(cache_path(song)?, bytes)?;
+
+    prune_cache()
+}
@@ -0,0 +1,146 @@
+use color_eyre::eyre::eyre;
+use std::{
+    env,
+    env::home_dir,
+    ffi::OsString,
+    fs,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+const APP_NAME: &str = "tui-music-player";
+
+fn xdg_dir(var: &str, fallback: &str) -> color_eyre::Result<PathBuf> {
+    let mut dir = match env::var_os(var) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let mut home = home_dir().ok_or_else(|| eyre!("Home directory not found."))?;
+            home.push(fallback);
+            home
+        }
+    };
+
+    dir.push(APP_NAME);
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
+
+pub fn data_dir() -> color_eyre::Result<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// For files that can be made again, like waveform overviews.
+pub fn cache_dir() -> color_eyre::Result<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+pub fn playlists_dir() -> color_eyre::Result<PathBuf> {
+    let mut dir = data_dir()?;
+    dir.push("playlists");
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
+
+pub fn config_dir() -> color_eyre::Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+pub fn themes_dir() -> color_eyre::Result<PathBuf> {
+    let mut dir = config_dir()?;
+    dir.push("themes");
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
+
+/// A path as one field of a tab-separated line. Tabs, newlines and
+/// backslashes are escaped with a backslash, bytes that aren't UTF-8 as
+/// `\xHH`, so every path survives [`unescape`].
+pub fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+
+    for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{byte:02x}"));
+        }
+    }
+
+    escaped
+}
+
+/// Reverses [`escape`]. A backslash before anything else is kept, as files
+/// written before paths were escaped have them unescaped.
+pub fn unescape(field: &str) -> PathBuf {
+    let mut bytes = vec![];
+    let mut rest = field;
+
+    while let Some(start) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..start]);
+        rest = &rest[start..];
+
+        let (byte, len) = match rest.as_bytes().get(1) {
+            Some(b'\\') => (b'\\', 2),
+            Some(b't') => (b'\t', 2),
+            Some(b'n') => (b'\n', 2),
+            Some(b'r') => (b'\r', 2),
+            Some(b'x') => match rest
+                .get(2..4)
+                .filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                Some(byte) => (byte, 4),
+                None => (b'\\', 1),
+            },
+            _ => (b'\\', 1),
+        };
+
+        bytes.push(byte);
+        rest = &rest[len..];
+    }
+
+    bytes.extend_from_slice(rest.as_bytes());
+
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_paths_survive_a_reload() {
+        for path in [
+            &b"/music/Caf\xc3\xa9.flac"[..],
+            b"/music/Caf\xe9.flac",
+            b"/music/tab\there\nnewline\r.mp3",
+            b"/music/back\\slash \\x41.mp3",
+            b"/music/\xff\xfe",
+        ] {
+            let path = PathBuf::from(OsString::from_vec(path.to_vec()));
+            let escaped = escape(&path);
+
+            assert!(!escaped.contains(['\t', '\n', '\r']));
+            assert_eq!(unescape(&escaped), path);
+        }
+    }
+
+    #[test]
+    fn reads_unescaped_paths() {
+        assert_eq!(
+            unescape("C:\\Music\\a.mp3"),
+            PathBuf::from("C:\\Music\\a.mp3")
+        );
+    }
+}
@@ -1,7 +1,30 @@
-use rodio::{source::EmptyCallback, Decoder, Sink};
-use std::{fs::File, io::BufReader, path::PathBuf, sync::mpsc};
+use rodio::{source::Empty, Source};
+use std::{
+    collections::VecDeque,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
 
-use crate::player_controller_message::{ControllerCommand, PlayerControllerCommand};
+use crate::{
+    config::{OutputBackend, OutputConfig, PlaybackConfig, ReplayGainMode},
+    eq::{EqHandle, Equalize},
+    format,
+    gapless::{
+        GaplessHandle, GaplessQueue, Preroll, QueuedTrack, Track, TrackDetails, TrackEvent,
+        TrackPosition,
+    },
+    output::{Output, Pipeline},
+    player_controller_message::{ControllerCommand, ControllerSender, PlayerControllerCommand},
+    player_message::PlayerMessage,
+    scanner,
+    song::Song,
+    speed::{clamp_speed, SpeedHandle, SpeedMemory, TimeStretch},
+    visualizer::Tap,
+};
+
+/// ReplayGain in dB is kept within this, a broken tag or measurement
+/// shouldn't blow up the volume.
+const MAX_REPLAY_GAIN: f32 = 24.0;
 
 #[derive(Clone)]
 pub enum PlayerState {
@@ -15,21 +38,84 @@ pub enum PlayerCommand {
     PlayPause,
     Skip,
     Stop,
+    SpeedUp,
+    SpeedDown,
+    ResetSpeed,
+    /// Sets the speed of everything that isn't spoken-word content, used
+    /// to carry it over when the player is restarted.
+    SetMusicSpeed(f32),
+    /// Switches to the named output device, None for the system default.
+    SetDevice(Option<String>),
+    /// The output device failed, e.g. it was unplugged.
+    DeviceLost,
+    /// Jumps to a position in the current track.
+    Seek(Duration),
+    /// Skips to the nth song after the current one.
+    SkipTo(usize),
+    /// Sets the volume between 0 and 1.
+    SetVolume(f32),
+    /// Replaces the songs after the current one, e.g. after shuffling.
+    Reorder(Vec<Song>),
+    /// Sent by the gapless queue to get the next song decoded in time.
+    Preload,
+    /// Stops playback and ends the player thread.
+    Shutdown,
 }
 
 pub struct Player {
     player_state: PlayerState,
-    sink: Option<Sink>,
-    pub tx: mpsc::Sender<PlayerControllerCommand>,
+    pipeline: Option<Pipeline>,
+    output: Option<Output>,
+    /// Output chosen by the user.
+    output_config: OutputConfig,
+    gapless: Option<GaplessHandle>,
+    /// Songs waiting to be opened, only the next one is decoded ahead.
+    pending: VecDeque<Song>,
+    config: PlaybackConfig,
+    equalizer: EqHandle,
+    tap: Tap,
+    position: TrackPosition,
+    /// Album of the song preloaded last, for the automatic ReplayGain mode.
+    last_album: Option<String>,
+    /// Speed the output is played at right now.
+    speed: SpeedHandle,
+    /// Speed for everything that isn't spoken-word content.
+    music_speed: SpeedHandle,
+    speed_memory: SpeedMemory,
+    current: Arc<Mutex<Option<TrackDetails>>>,
+    pub tx: ControllerSender,
+    /// The player thread's own channel, for messages from the audio thread.
+    player_tx: mpsc::Sender<PlayerMessage>,
 }
 
 impl Player {
-    pub fn new(tx: mpsc::Sender<PlayerControllerCommand>) -> Player {
-        Player {
+    pub fn new(
+        tx: ControllerSender,
+        player_tx: mpsc::Sender<PlayerMessage>,
+        config: PlaybackConfig,
+        equalizer: EqHandle,
+        tap: Tap,
+        position: TrackPosition,
+    ) -> color_eyre::Result<Player> {
+        Ok(Player {
             player_state: PlayerState::Paused,
-            sink: None,
+            pipeline: None,
+            output: None,
+            output_config: OutputConfig::default(),
+            gapless: None,
+            pending: VecDeque::new(),
+            config,
+            equalizer,
+            tap,
+            position,
+            last_album: None,
+            speed: SpeedHandle::new(1.0),
+            music_speed: SpeedHandle::new(1.0),
+            speed_memory: SpeedMemory::load()?,
+            current: Arc::new(Mutex::new(None)),
             tx,
-        }
+            player_tx,
+        })
     }
 
     pub fn get_player_state(&self) -> color_eyre::Result<PlayerState> {
@@ -41,17 +127,139 @@ impl Player {
         Ok(())
     }
 
-    pub fn set_sink(&mut self, sink: Sink) {
-        self.sink = Some(sink);
+    /// Builds the gapless signal chain and starts playing it on the output
+    /// chosen in `output_config`. The chain runs at the format of the first
+    /// output, later devices convert it.
+    pub fn start(&mut self, output_config: OutputConfig) -> color_eyre::Result<()> {
+        self.output_config = output_config;
+        self.connect()?;
+
+        let (channels, sample_rate) = match &self.output {
+            Some(output) => (output.get_channels(), output.get_sample_rate()),
+            None => (2, 44_100),
+        };
+
+        let tx = self.tx.clone();
+        let player_tx = self.player_tx.clone();
+        let speed = self.speed.clone();
+        let music_speed = self.music_speed.clone();
+        let speed_memory = self.speed_memory.clone();
+        let current = self.current.clone();
+
+        // Asks the player thread to preload the next song whenever a track
+        // starts or ends.
+        let (queue, handle) = GaplessQueue::new(
+            channels,
+            sample_rate,
+            self.position.clone(),
+            Box::new(move |event| {
+                let _ = tx.send(match event {
+                    TrackEvent::Started(details) => {
+                        let remembered = match details.spoken {
+                            true => speed_memory.get(&details.song.get_path()),
+                            false => None,
+                        };
+
+                        speed.set(remembered.unwrap_or_else(|| music_speed.get()));
+
+                        let gain = details.gain;
+
+                        if let Ok(mut current) = current.lock() {
+                            *current = Some(details);
+                        }
+
+                        PlayerControllerCommand::track_started(gain, speed.get(), music_speed.get())
+                    }
+                    TrackEvent::Ended => {
+                        if let Ok(mut current) = current.lock() {
+                            *current = None;
+                        }
+
+                        PlayerControllerCommand::new(ControllerCommand::PopQueue, None)
+                    }
+                });
+
+                let _ = player_tx.send(PlayerMessage::new(PlayerCommand::Preload, None));
+            }),
+        );
+
+        handle.set_crossfade(self.config.get_crossfade());
+
+        let pipeline = Pipeline::new(
+            Equalize::new(
+                TimeStretch::new(queue, self.speed.clone()),
+                self.equalizer.clone(),
+            ),
+            self.tap.clone(),
+        );
+
+        if let Some(output) = &mut self.output {
+            output.play(&pipeline);
+        }
+
+        self.pipeline = Some(pipeline);
+        self.gapless = Some(handle);
+
+        Ok(())
+    }
+
+    pub fn has_output(&self) -> bool {
+        self.output.is_some()
+    }
+
+    /// Switches to another device, playback continues where it was.
+    pub fn set_device(&mut self, device: Option<String>) -> color_eyre::Result<()> {
+        self.output_config.backend = OutputBackend::Device;
+        self.output_config.device = device;
+        self.reconnect()
+    }
+
+    /// Reopens the chosen device, falling back to the system default when
+    /// it is gone. Without any device the player keeps its queue and the
+    /// player thread tries again later.
+    pub fn reconnect(&mut self) -> color_eyre::Result<()> {
+        // The old stream has to be closed before the device can be reopened.
+        self.output = None;
+        self.connect()?;
+
+        if let (Some(output), Some(pipeline)) = (&mut self.output, &self.pipeline) {
+            output.play(pipeline);
+        }
+
+        Ok(())
+    }
+
+    fn connect(&mut self) -> color_eyre::Result<()> {
+        let player_tx = self.player_tx.clone();
+        let tx = self.tx.clone();
+
+        self.output = Output::open(
+            &self.output_config,
+            move || {
+                let _ = player_tx.send(PlayerMessage::new(PlayerCommand::DeviceLost, None));
+            },
+            move |error| {
+                let _ = tx.send(PlayerControllerCommand::error(error));
+            },
+        )
+        .ok();
+
+        self.tx.send(PlayerControllerCommand::device_changed(
+            self.output
+                .as_ref()
+                .map(|output| output.get_device().to_string()),
+        ))?;
+
+        Ok(())
     }
 
     pub fn play_pause(&mut self) -> color_eyre::Result<()> {
-        if let Some(sink) = &self.sink {
-            if sink.is_paused() {
-                sink.play();
+        if let Some(pipeline) = &self.pipeline {
+            if pipeline.is_paused() {
+                pipeline.set_paused(false);
                 self.set_player_state(PlayerState::Playing)?;
             } else {
-                sink.pause();
+                pipeline.set_paused(true);
                 self.set_player_state(PlayerState::Paused)?;
             }
         }
@@ -59,43 +267,228 @@ impl Player {
         Ok(())
     }
 
-    pub fn add_to_queue(&self, song: PathBuf) -> color_eyre::Result<()> {
-        let source = Decoder::new(BufReader::new(File::open(song)?))?;
+    pub fn add_to_queue(&mut self, song: Song) -> color_eyre::Result<()> {
+        self.pending.push_back(song);
+        self.preload();
 
-        if let Some(sink) = &self.sink {
-            sink.append(source);
-            sink.play();
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.set_paused(false);
         }
 
         Ok(())
     }
 
-    pub fn skip(&self) {
-        if let Some(sink) = &self.sink {
-            sink.skip_one();
+    /// Opens and pre-decodes the next pending song, unless one is already
+    /// waiting behind the current track.
+    pub fn preload(&mut self) {
+        let Some(gapless) = &self.gapless else {
+            return;
+        };
+
+        if gapless.upcoming_len() > 0 {
+            return;
+        }
+
+        if let Some(song) = self.pending.pop_front() {
+            // A song that fails to open is skipped, but still takes its
+            // place in the queue so the controller's queue stays in step
+            // with playback.
+            let album = album_of(&song);
+            let gain = self.replay_gain(&song, &album);
+
+            let source = match open_song(&song, Duration::ZERO, gain) {
+                Ok(source) => source,
+                Err(e) => {
+                    let _ = self.tx.send(PlayerControllerCommand::error(format!(
+                        "Can't play {}: {e}",
+                        song.get_path().display()
+                    )));
+
+                    Box::new(Empty::new())
+                }
+            };
+
+            gapless.push(QueuedTrack {
+                source,
+                album: album.clone(),
+                details: TrackDetails {
+                    spoken: format::is_spoken_word(&song.get_path()),
+                    song,
+                    gain,
+                },
+            });
+
+            self.last_album = album;
         }
     }
 
-    pub fn stop(&self) {
-        if let Some(sink) = &self.sink {
-            sink.stop();
+    /// Gain in dB to apply to `song`, None if ReplayGain is off or the song
+    /// has no ReplayGain tags.
+    fn replay_gain(&self, song: &Song, album: &Option<String>) -> Option<f32> {
+        let use_album = match self.config.replay_gain {
+            ReplayGainMode::Off => return None,
+            ReplayGainMode::Track => false,
+            ReplayGainMode::Album => true,
+            ReplayGainMode::Auto => {
+                album.is_some()
+                    && (*album == self.last_album
+                        || self.pending.front().map(album_of) == Some(album.clone()))
+            }
+        };
+
+        let path = song.get_path();
+
+        // Files without tags may have been measured by the scanner.
+        let tags = match format::read_replay_gain(&path) {
+            Ok(tags) if tags.track_gain.is_some() || tags.album_gain.is_some() => tags,
+            _ => scanner::cached_gain(&path)?,
+        };
+
+        let (gain, peak) = if use_album {
+            (
+                tags.album_gain.or(tags.track_gain),
+                tags.album_peak.or(tags.track_peak),
+            )
+        } else {
+            (
+                tags.track_gain.or(tags.album_gain),
+                tags.track_peak.or(tags.album_peak),
+            )
+        };
+
+        let mut gain = gain.filter(|gain| gain.is_finite())? + self.config.preamp;
+
+        if self.config.clipping_prevention
+            && let Some(peak) = peak
+            && peak.is_finite()
+            && peak > 0.0
+        {
+            gain = gain.min(-20.0 * peak.log10());
         }
+
+        gain.is_finite()
+            .then(|| gain.clamp(-MAX_REPLAY_GAIN, MAX_REPLAY_GAIN))
     }
 
-    pub fn add_callback(&self) -> color_eyre::Result<()> {
-        let tx = self.tx.clone();
+    /// Changes the speed by `delta`, None goes back to normal speed. The
+    /// speed of spoken-word content is remembered for the file.
+    pub fn change_speed(&mut self, delta: Option<f32>) -> color_eyre::Result<()> {
+        let speed = match delta {
+            Some(delta) => clamp_speed(self.speed.get() + delta),
+            None => 1.0,
+        };
+
+        self.speed.set(speed);
 
-        let callback = EmptyCallback::new(Box::new(move || {
-            let _ = tx.send(PlayerControllerCommand::new(
-                ControllerCommand::PopQueue,
-                None,
-            ));
-        }));
+        let current = self.current.lock().ok().and_then(|current| current.clone());
 
-        if let Some(sink) = &self.sink {
-            sink.append(callback);
+        match current {
+            Some(details) if details.spoken => {
+                self.speed_memory.set(&details.song.get_path(), speed)?
+            }
+            _ => self.music_speed.set(speed),
         }
 
+        self.tx.send(PlayerControllerCommand::speed_changed(
+            self.speed.get(),
+            self.music_speed.get(),
+        ))?;
+
         Ok(())
     }
+
+    pub fn set_music_speed(&mut self, speed: f32) {
+        self.music_speed.set(clamp_speed(speed));
+    }
+
+    /// Jumps to `position` in the current track by opening it again there.
+    pub fn seek(&self, position: Duration) -> color_eyre::Result<()> {
+        let current = self.current.lock().ok().and_then(|current| current.clone());
+
+        if let (Some(gapless), Some(details)) = (&self.gapless, current) {
+            gapless.seek(open_song(&details.song, position, details.gain)?, position);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        if let Some(pipeline) = &self.pipeline {
+            pipeline.set_volume(volume);
+        }
+    }
+
+    /// Plays `songs` after the current track instead of the songs queued
+    /// so far, including one that was already preloaded.
+    pub fn reorder(&mut self, songs: Vec<Song>) {
+        if let Some(gapless) = &self.gapless {
+            gapless.clear_upcoming();
+        }
+
+        self.pending = songs.into();
+        self.preload();
+    }
+
+    pub fn skip(&self) {
+        if let Some(gapless) = &self.gapless {
+            gapless.skip();
+        }
+    }
+
+    /// Skips to the `count`th song after the current one, the songs in
+    /// between are dropped without playing.
+    pub fn skip_to(&mut self, count: usize) {
+        let Some(gapless) = &self.gapless else {
+            return;
+        };
+
+        let mut dropped = count.saturating_sub(1);
+
+        if dropped > 0 {
+            dropped -= gapless.clear_upcoming().min(dropped);
+        }
+
+        self.pending.drain(..dropped.min(self.pending.len()));
+        self.preload();
+
+        if let Some(gapless) = &self.gapless {
+            gapless.skip();
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.pending.clear();
+        self.last_album = None;
+
+        if let Some(gapless) = &self.gapless {
+            gapless.stop();
+        }
+    }
+}
+
+fn album_of(song: &Song) -> Option<String> {
+    let album = format::read_tags(&song.get_path())
+        .ok()
+        .and_then(|tags| tags.album);
+
+    // Tracks cut from one file by a CUE sheet belong together even without
+    // an album tag.
+    match album {
+        Some(album) => Some(album),
+        None if song.get_start().is_some() => Some(song.get_path().display().to_string()),
+        None => None,
+    }
+}
+
+/// Opens `song` `offset` into the track with `gain` dB applied, and decodes
+/// its beginning ahead of time.
+fn open_song(song: &Song, offset: Duration, gain: Option<f32>) -> color_eyre::Result<Track> {
+    let track = song.open(offset)?;
+
+    Ok(match gain {
+        Some(gain) => Box::new(Preroll::new(Box::new(
+            track.amplify(10f32.powf(gain / 20.0)),
+        ))),
+        None => Box::new(Preroll::new(track)),
+    })
 }
@@ -1,102 +1,363 @@
 use crate::{
+    app::AppEvent,
+    config::{OutputBackend, OutputConfig, PlaybackConfig},
+    eq::EqHandle,
+    gapless::TrackPosition,
     player::{Player, PlayerCommand, PlayerState},
-    player_controller_message::{ControllerCommand, PlayerControllerCommand},
+    player_controller_message::{ControllerCommand, ControllerSender, PlayerControllerCommand},
     player_message::PlayerMessage,
+    playlist::PlaylistEntry,
     queue::Queue,
     song::Song,
+    speed::SPEED_STEP,
+    visualizer::Tap,
 };
 use color_eyre::eyre::eyre;
-use std::{sync::mpsc, sync::mpsc::TryRecvError, thread};
+use std::{
+    any::Any,
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError, TryRecvError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How often the player looks for an output device while it has none.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Shortest time between two restarts of the player thread, so a player
+/// that fails right away doesn't keep a core busy.
+const RESTART_INTERVAL: Duration = Duration::from_secs(5);
 
 pub struct PlayerController {
     sender: mpsc::Sender<PlayerMessage>,
     receiver: Option<mpsc::Receiver<PlayerControllerCommand>>,
+    /// The player thread, watched so it can be restarted when it dies.
+    player: Option<JoinHandle<color_eyre::Result<()>>>,
+    last_restart: Option<Instant>,
+    /// Position to seek to once the restarted player plays the current
+    /// song again.
+    resume_at: Option<Duration>,
+    /// What the player thread is started with.
+    config: PlaybackConfig,
+    output_config: OutputConfig,
+    equalizer: EqHandle,
+    tap: Tap,
+    /// Woken up whenever the player sends a message.
+    wake: mpsc::Sender<AppEvent>,
     player_state: PlayerState,
     pub queue: Queue,
+    played: Vec<PathBuf>,
+    /// Errors of the player thread not shown yet.
+    errors: Vec<String>,
+    /// ReplayGain applied to the current song.
+    gain: Option<f32>,
+    speed: f32,
+    /// Speed for everything that isn't spoken-word content, restored when
+    /// the player is restarted.
+    music_speed: f32,
+    /// Output device in use, None while no device could be opened.
+    device: Option<String>,
+    position: TrackPosition,
+    /// Volume in percent.
+    volume: u8,
+    /// Songs added while shuffling go to a random place in the queue.
+    shuffle: bool,
 }
 
 impl PlayerController {
-    pub fn new() -> color_eyre::Result<PlayerController> {
-        let (tx, rx) = mpsc::channel::<PlayerMessage>();
-
+    pub fn new(
+        config: PlaybackConfig,
+        output_config: OutputConfig,
+        equalizer: EqHandle,
+        tap: Tap,
+        wake: mpsc::Sender<AppEvent>,
+    ) -> color_eyre::Result<PlayerController> {
         let mut pc = PlayerController {
-            sender: tx,
+            sender: mpsc::channel().0,
             receiver: None,
+            player: None,
+            last_restart: None,
+            resume_at: None,
+            config,
+            output_config,
+            equalizer,
+            tap,
+            wake,
             player_state: PlayerState::Paused,
             queue: Queue::new(),
+            played: vec![],
+            errors: vec![],
+            gain: None,
+            speed: 1.0,
+            music_speed: 1.0,
+            device: None,
+            position: TrackPosition::default(),
+            volume: 100,
+            shuffle: false,
         };
 
-        pc.init_player(rx)?;
+        pc.init_player();
 
         Ok(pc)
     }
 
-    fn init_player(&mut self, rx: mpsc::Receiver<PlayerMessage>) -> color_eyre::Result<()> {
+    /// Starts a new player thread with fresh channels.
+    fn init_player(&mut self) {
+        let (player_tx, rx) = mpsc::channel::<PlayerMessage>();
         let (tx, receiver) = mpsc::channel();
+        let config = self.config.clone();
+        let output_config = self.output_config.clone();
+        let equalizer = self.equalizer.clone();
+        let tap = self.tap.clone();
+        let position = self.position.clone();
+        let tx = ControllerSender::new(tx, Some(self.wake.clone()));
 
+        self.sender = player_tx.clone();
         self.receiver = Some(receiver);
 
-        thread::spawn(move || -> color_eyre::Result<()> {
-            let mut player = Player::new(tx);
-
-            let stream_handle = rodio::OutputStreamBuilder::open_default_stream()
-                .expect("open default audio stream");
+        let player = thread::Builder::new().name(String::from("player"));
 
-            let sink = rodio::Sink::connect_new(stream_handle.mixer());
+        let spawned = player.spawn(move || -> color_eyre::Result<()> {
+            let mut player = Player::new(tx, player_tx, config, equalizer, tap, position)?;
 
-            player.set_sink(sink);
+            player.start(output_config)?;
 
             loop {
-                let command = rx.recv()?;
-
-                match command.get_command() {
-                    PlayerCommand::Play => {
-                        if let Some(message) = command.get_message()? {
-                            player.add_to_queue(message.clone())?;
-
-                            player.set_player_state(PlayerState::Playing)?;
-
-                            player.add_callback()?;
+                let command = if player.has_output() {
+                    match rx.recv() {
+                        Ok(command) => command,
+                        Err(_) => return Ok(()),
+                    }
+                } else {
+                    match rx.recv_timeout(RECONNECT_INTERVAL) {
+                        Ok(command) => command,
+                        Err(RecvTimeoutError::Timeout) => {
+                            player.reconnect()?;
+                            continue;
                         }
+                        Err(RecvTimeoutError::Disconnected) => return Ok(()),
                     }
-                    PlayerCommand::PlayPause => player.play_pause()?,
-                    PlayerCommand::Skip => player.skip(),
-                    PlayerCommand::Stop => player.stop(),
+                };
+
+                // The player's own messages keep the channel open, so it
+                // has to be told when to end.
+                let shutdown = matches!(command.get_command(), PlayerCommand::Shutdown);
+
+                // A failed command, e.g. a seek in a file that went away,
+                // doesn't end playback.
+                if let Err(e) = run_command(&mut player, command) {
+                    player
+                        .tx
+                        .send(PlayerControllerCommand::error(e.to_string()))?;
                 }
 
-                player.tx.send(PlayerControllerCommand::new(
-                    ControllerCommand::UpdateState,
-                    Some(player.get_player_state()?),
-                ))?;
+                if shutdown {
+                    return Ok(());
+                }
             }
         });
 
+        match spawned {
+            Ok(player) => self.player = Some(player),
+            Err(e) => self.errors.push(format!("Can't start the player. {e}")),
+        }
+    }
+
+    /// Starts the player again after its thread ended, with the queue,
+    /// position and volume it had.
+    fn restart_player(&mut self) -> color_eyre::Result<()> {
+        if self
+            .last_restart
+            .is_some_and(|time| time.elapsed() < RESTART_INTERVAL)
+        {
+            return Ok(());
+        }
+
+        let reason = match self.player.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => e.to_string(),
+            Some(Err(panic)) => panic_message(panic),
+            Some(Ok(Ok(()))) | None => String::from("it quit"),
+        };
+
+        self.errors
+            .push(format!("The player stopped ({reason}), restarting it."));
+
+        let songs = self.queue.get_queue()?;
+        let position = self.get_position();
+        let paused = matches!(self.player_state, PlayerState::Paused);
+
+        self.last_restart = Some(Instant::now());
+        self.player_state = PlayerState::Paused;
+        self.gain = None;
+        self.init_player();
+
+        if self.volume < 100 {
+            self.set_volume(self.volume)?;
+        }
+
+        if self.music_speed != 1.0 {
+            self.send_command(PlayerMessage::new(
+                PlayerCommand::SetMusicSpeed(self.music_speed),
+                None,
+            ))?;
+        }
+
+        self.resume(&songs, position, paused)
+    }
+
+    /// Queues the songs of an earlier session, paused at `position` in the
+    /// first one.
+    pub fn restore(&mut self, songs: Vec<Song>, position: Duration) -> color_eyre::Result<()> {
+        for song in songs.iter() {
+            self.queue.add(song.clone());
+        }
+
+        self.resume(&songs, position, true)
+    }
+
+    /// Hands songs that are already in the controller's queue to the
+    /// player, the first one continues at `position`.
+    fn resume(
+        &mut self,
+        songs: &[Song],
+        position: Duration,
+        paused: bool,
+    ) -> color_eyre::Result<()> {
+        for song in songs {
+            self.sender
+                .send(PlayerMessage::new(PlayerCommand::Play, Some(song.clone())))?;
+        }
+
+        if !songs.is_empty() && paused {
+            self.toggle()?;
+        }
+
+        self.resume_at = Some(position).filter(|position| !position.is_zero());
+
         Ok(())
     }
 
+    /// Stops playback and waits for the player thread to end, so the
+    /// output is closed properly before the program quits.
+    pub fn shutdown(&mut self) {
+        let _ = self
+            .sender
+            .send(PlayerMessage::new(PlayerCommand::Shutdown, None));
+
+        if let Some(player) = self.player.take() {
+            let _ = player.join();
+        }
+    }
+
+    fn is_player_running(&self) -> bool {
+        self.player
+            .as_ref()
+            .is_some_and(|player| !player.is_finished())
+    }
+
     pub fn send_command(&mut self, command: PlayerMessage) -> color_eyre::Result<()> {
         self.sender.send(command.clone())?;
 
         match command.get_command() {
             PlayerCommand::Play => {
-                if let Some(path) = command.get_message()? {
-                    self.queue.add(Song::new(path));
+                if let Some(song) = command.get_message()? {
+                    if self.shuffle {
+                        self.queue.insert_randomly(song);
+                        self.send_upcoming()?;
+                    } else {
+                        self.queue.add(song);
+                    }
                 }
             }
-            PlayerCommand::Stop => self.queue.clear(),
+            PlayerCommand::Stop => {
+                self.queue.clear();
+                self.gain = None;
+            }
+            // The current song is popped once it ended.
+            PlayerCommand::SkipTo(count) => self.queue.remove(1..count),
             _ => {}
         }
 
         Ok(())
     }
 
-    pub fn check_for_message(&mut self) -> color_eyre::Result<()> {
-        if let Some(rx) = &self.receiver {
+    /// Tells the player the order of the songs after the current one.
+    fn send_upcoming(&mut self) -> color_eyre::Result<()> {
+        let upcoming = self.queue.get_queue()?.into_iter().skip(1).collect();
+
+        self.send_command(PlayerMessage::new(PlayerCommand::Reorder(upcoming), None))
+    }
+
+    pub fn play_song(&mut self, song: Song) -> color_eyre::Result<()> {
+        self.send_command(PlayerMessage::new(PlayerCommand::Play, Some(song)))
+    }
+
+    /// Queues several songs. While shuffling they are all placed first and
+    /// the player gets the new order once, instead of once per song.
+    pub fn play_songs(&mut self, songs: impl IntoIterator<Item = Song>) -> color_eyre::Result<()> {
+        let mut added = false;
+
+        for song in songs {
+            self.sender
+                .send(PlayerMessage::new(PlayerCommand::Play, Some(song.clone())))?;
+
+            if self.shuffle {
+                self.queue.insert_randomly(song);
+            } else {
+                self.queue.add(song);
+            }
+
+            added = true;
+        }
+
+        if added && self.shuffle {
+            self.send_upcoming()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_playlist(
+        &mut self,
+        entries: Vec<PlaylistEntry>,
+        replace: bool,
+    ) -> color_eyre::Result<()> {
+        if replace {
+            self.stop()?;
+        }
+
+        self.play_songs(
+            entries
+                .into_iter()
+                .filter(|entry| entry.get_path().is_file())
+                .map(PlaylistEntry::into_song),
+        )?;
+
+        Ok(())
+    }
+
+    /// Handles every message the player thread sent since the last call,
+    /// returns whether there were any.
+    pub fn check_for_message(&mut self) -> color_eyre::Result<bool> {
+        let mut received = false;
+
+        loop {
+            let Some(rx) = &self.receiver else {
+                return Err(eyre!("Channel does not exist!"));
+            };
+
             let pcc = match rx.try_recv() {
                 Ok(message) => message,
-                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return Ok(()),
+                Err(TryRecvError::Empty) if self.is_player_running() => return Ok(received),
+                // Messages sent before the thread ended are handled first.
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {
+                    self.restart_player()?;
+                    return Ok(true);
+                }
             };
 
+            received = true;
+
             match pcc.get_command() {
                 ControllerCommand::UpdateState => {
                     if let Some(PlayerState::Playing) = pcc.get_state()? {
@@ -106,14 +367,75 @@ impl PlayerController {
                     }
                 }
                 ControllerCommand::PopQueue => {
+                    if let Some(song) = self.queue.get_current_song()? {
+                        self.played.push(song.get_path());
+                    }
+
                     self.queue.pop();
+                    self.gain = None;
                 }
+                ControllerCommand::TrackStarted => {
+                    self.gain = pcc.get_gain();
+                    self.speed = pcc.get_speed().unwrap_or(self.speed);
+                    self.music_speed = pcc.get_music_speed().unwrap_or(self.music_speed);
+
+                    if let Some(position) = self.resume_at.take() {
+                        self.seek(position)?;
+                    }
+                }
+                ControllerCommand::UpdateSpeed => {
+                    self.speed = pcc.get_speed().unwrap_or(self.speed);
+                    self.music_speed = pcc.get_music_speed().unwrap_or(self.music_speed);
+                }
+                ControllerCommand::DeviceChanged => self.device = pcc.get_device(),
+                ControllerCommand::Error => self.errors.extend(pcc.get_error()),
             }
-        } else {
-            return Err(eyre!("Channel does not exist!"));
         }
+    }
 
-        Ok(())
+    /// Songs that finished playing since the last call.
+    pub fn take_played(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.played)
+    }
+
+    /// Errors of the player thread since the last call.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn get_replay_gain(&self) -> Option<f32> {
+        self.gain
+    }
+
+    pub fn get_speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn get_position(&self) -> Duration {
+        self.position.get_position()
+    }
+
+    /// Length of the current track, None if nothing plays or the decoder
+    /// doesn't know it.
+    pub fn get_length(&self) -> Option<Duration> {
+        self.position.get_length()
+    }
+
+    pub fn get_device(&self) -> Option<&str> {
+        self.device.as_deref()
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.shuffle
+    }
+
+    /// Whether a song is playing right now, not paused and not stopped.
+    pub fn is_playing(&self) -> bool {
+        matches!(self.player_state, PlayerState::Playing) && !self.queue.is_empty()
     }
 
     pub fn get_player_state_as_string(&self) -> color_eyre::Result<String> {
@@ -140,4 +462,107 @@ impl PlayerController {
 
         Ok(())
     }
+
+    pub fn speed_up(&mut self) -> color_eyre::Result<()> {
+        self.send_command(PlayerMessage::new(PlayerCommand::SpeedUp, None))
+    }
+
+    pub fn speed_down(&mut self) -> color_eyre::Result<()> {
+        self.send_command(PlayerMessage::new(PlayerCommand::SpeedDown, None))
+    }
+
+    pub fn reset_speed(&mut self) -> color_eyre::Result<()> {
+        self.send_command(PlayerMessage::new(PlayerCommand::ResetSpeed, None))
+    }
+
+    /// Plays on the named device from now on, None for the system default.
+    pub fn set_device(&mut self, device: Option<String>) -> color_eyre::Result<()> {
+        // A restarted player opens the same device.
+        self.output_config.backend = OutputBackend::Device;
+        self.output_config.device = device.clone();
+
+        self.send_command(PlayerMessage::new(PlayerCommand::SetDevice(device), None))
+    }
+
+    pub fn seek(&mut self, position: Duration) -> color_eyre::Result<()> {
+        self.send_command(PlayerMessage::new(PlayerCommand::Seek(position), None))
+    }
+
+    /// Sets the volume in percent, at most 100.
+    pub fn set_volume(&mut self, volume: u8) -> color_eyre::Result<()> {
+        self.volume = volume.min(100);
+
+        self.send_command(PlayerMessage::new(
+            PlayerCommand::SetVolume(self.volume as f32 / 100.0),
+            None,
+        ))
+    }
+
+    /// Turning shuffling on also shuffles the songs already queued.
+    pub fn set_shuffle(&mut self, shuffle: bool) -> color_eyre::Result<()> {
+        self.shuffle = shuffle;
+
+        if shuffle {
+            self.queue.shuffle();
+            self.send_upcoming()?;
+        }
+
+        Ok(())
+    }
+
+    /// Plays the queue entry at `index` right away.
+    pub fn skip_to(&mut self, index: usize) -> color_eyre::Result<()> {
+        if index == 0 || index >= self.queue.get_queue()?.len() {
+            return Ok(());
+        }
+
+        self.send_command(PlayerMessage::new(PlayerCommand::SkipTo(index), None))
+    }
+}
+
+/// Carries out a command in the player thread and reports the new state.
+fn run_command(player: &mut Player, command: PlayerMessage) -> color_eyre::Result<()> {
+    match command.get_command() {
+        PlayerCommand::Play => {
+            if let Some(song) = command.get_message()? {
+                player.add_to_queue(song)?;
+
+                player.set_player_state(PlayerState::Playing)?;
+            }
+        }
+        PlayerCommand::PlayPause => player.play_pause()?,
+        PlayerCommand::Skip => player.skip(),
+        PlayerCommand::Stop | PlayerCommand::Shutdown => player.stop(),
+        PlayerCommand::SpeedUp => player.change_speed(Some(SPEED_STEP))?,
+        PlayerCommand::SpeedDown => player.change_speed(Some(-SPEED_STEP))?,
+        PlayerCommand::ResetSpeed => player.change_speed(None)?,
+        PlayerCommand::SetMusicSpeed(speed) => player.set_music_speed(speed),
+        PlayerCommand::SetDevice(device) => player.set_device(device)?,
+        PlayerCommand::DeviceLost => player.reconnect()?,
+        PlayerCommand::Seek(position) => player.seek(position)?,
+        PlayerCommand::SkipTo(count) => player.skip_to(count),
+        PlayerCommand::SetVolume(volume) => player.set_volume(volume),
+        PlayerCommand::Reorder(songs) => player.reorder(songs),
+        PlayerCommand::Preload => {
+            player.preload();
+            return Ok(());
+        }
+    }
+
+    player.tx.send(PlayerControllerCommand::new(
+        ControllerCommand::UpdateState,
+        Some(player.get_player_state()?),
+    ))?;
+
+    Ok(())
+}
+
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => panic.downcast_ref::<&str>().map_or_else(
+            || String::from("it panicked"),
+            |message| message.to_string(),
+        ),
+    }
 }
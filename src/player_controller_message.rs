@@ -1,19 +1,125 @@
-use crate::player::PlayerState;
+use crate::{app::AppEvent, player::PlayerState};
+use std::sync::mpsc;
 
 #[derive(Clone)]
 pub enum ControllerCommand {
     UpdateState,
     PopQueue,
+    /// A track started playing, carries the ReplayGain applied to it and
+    /// the speed it plays at.
+    TrackStarted,
+    UpdateSpeed,
+    /// The player now plays on the named device, None if no device could
+    /// be opened.
+    DeviceChanged,
+    /// Something failed in the player thread, playback goes on.
+    Error,
+}
+
+/// The player's end of the channel to the controller. Each message also
+/// wakes up the main loop, so it is handled right away.
+#[derive(Clone)]
+pub struct ControllerSender {
+    tx: mpsc::Sender<PlayerControllerCommand>,
+    wake: Option<mpsc::Sender<AppEvent>>,
+}
+
+impl ControllerSender {
+    pub fn new(
+        tx: mpsc::Sender<PlayerControllerCommand>,
+        wake: Option<mpsc::Sender<AppEvent>>,
+    ) -> ControllerSender {
+        ControllerSender { tx, wake }
+    }
+
+    pub fn send(
+        &self,
+        command: PlayerControllerCommand,
+    ) -> Result<(), mpsc::SendError<PlayerControllerCommand>> {
+        self.tx.send(command)?;
+
+        if let Some(wake) = &self.wake {
+            let _ = wake.send(AppEvent::Player);
+        }
+
+        Ok(())
+    }
 }
 
 pub struct PlayerControllerCommand {
     command: ControllerCommand,
     state: Option<PlayerState>,
+    gain: Option<f32>,
+    speed: Option<f32>,
+    /// Speed for everything that isn't spoken-word content.
+    music_speed: Option<f32>,
+    device: Option<String>,
+    error: Option<String>,
 }
 
 impl PlayerControllerCommand {
     pub fn new(command: ControllerCommand, state: Option<PlayerState>) -> PlayerControllerCommand {
-        PlayerControllerCommand { command, state }
+        PlayerControllerCommand {
+            command,
+            state,
+            gain: None,
+            speed: None,
+            music_speed: None,
+            device: None,
+            error: None,
+        }
+    }
+
+    pub fn track_started(
+        gain: Option<f32>,
+        speed: f32,
+        music_speed: f32,
+    ) -> PlayerControllerCommand {
+        PlayerControllerCommand {
+            command: ControllerCommand::TrackStarted,
+            state: None,
+            gain,
+            speed: Some(speed),
+            music_speed: Some(music_speed),
+            device: None,
+            error: None,
+        }
+    }
+
+    pub fn speed_changed(speed: f32, music_speed: f32) -> PlayerControllerCommand {
+        PlayerControllerCommand {
+            command: ControllerCommand::UpdateSpeed,
+            state: None,
+            gain: None,
+            speed: Some(speed),
+            music_speed: Some(music_speed),
+            device: None,
+            error: None,
+        }
+    }
+
+    pub fn device_changed(device: Option<String>) -> PlayerControllerCommand {
+        PlayerControllerCommand {
+            command: ControllerCommand::DeviceChanged,
+            state: None,
+            gain: None,
+            speed: None,
+            music_speed: None,
+            device,
+            error: None,
+        }
+    }
+
+    pub fn error(error: String) -> PlayerControllerCommand {
+        PlayerControllerCommand {
+            command: ControllerCommand::Error,
+            state: None,
+            gain: None,
+            speed: None,
+            music_speed: None,
+            device: None,
+            error: Some(error),
+        }
     }
 
     pub fn get_command(&self) -> ControllerCommand {
@@ -23,4 +129,24 @@ impl PlayerControllerCommand {
     pub fn get_state(&self) -> color_eyre::Result<Option<PlayerState>> {
         Ok(self.state.clone())
     }
+
+    pub fn get_gain(&self) -> Option<f32> {
+        self.gain
+    }
+
+    pub fn get_speed(&self) -> Option<f32> {
+        self.speed
+    }
+
+    pub fn get_music_speed(&self) -> Option<f32> {
+        self.music_speed
+    }
+
+    pub fn get_device(&self) -> Option<String> {
+        self.device.clone()
+    }
+
+    pub fn get_error(&self) -> Option<String> {
+        self.error.clone()
+    }
 }
@@ -1,14 +1,13 @@
-use crate::player::PlayerCommand;
-use std::path::PathBuf;
+use crate::{player::PlayerCommand, song::Song};
 
 #[derive(Clone)]
 pub struct PlayerMessage {
     command: PlayerCommand,
-    message: Option<PathBuf>,
+    message: Option<Song>,
 }
 
 impl PlayerMessage {
-    pub fn new(command: PlayerCommand, message: Option<PathBuf>) -> PlayerMessage {
+    pub fn new(command: PlayerCommand, message: Option<Song>) -> PlayerMessage {
         PlayerMessage { command, message }
     }
 
@@ -16,7 +15,7 @@ impl PlayerMessage {
         self.command.clone()
     }
 
-    pub fn get_message(&self) -> color_eyre::Result<Option<PathBuf>> {
+    pub fn get_message(&self) -> color_eyre::Result<Option<Song>> {
         Ok(self.message.clone())
     }
 }
@@ -0,0 +1,560 @@
+use color_eyre::eyre::eyre;
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use crate::song::Song;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PlaylistFormat {
+    M3u,
+    Pls,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub fn from_path(path: &Path) -> Option<PlaylistFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+
+        match ext.as_str() {
+            "m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+            "pls" => Some(PlaylistFormat::Pls),
+            "xspf" => Some(PlaylistFormat::Xspf),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u => "m3u8",
+            PlaylistFormat::Pls => "pls",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PlaylistEntry {
+    path: PathBuf,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+impl PlaylistEntry {
+    pub fn new(path: PathBuf, title: Option<String>, artist: Option<String>) -> PlaylistEntry {
+        PlaylistEntry {
+            path,
+            title,
+            artist,
+        }
+    }
+
+    pub fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn get_title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    pub fn get_artist(&self) -> Option<String> {
+        self.artist.clone()
+    }
+
+    pub fn into_song(self) -> Song {
+        let mut song = Song::new(self.path);
+
+        if let Some(title) = self.title {
+            song.set_title(title);
+        }
+
+        if let Some(artist) = self.artist {
+            song.set_artist(artist);
+        }
+
+        song
+    }
+}
+
+pub fn is_playlist(path: &Path) -> bool {
+    PlaylistFormat::from_path(path).is_some()
+}
+
+pub fn read(path: &Path) -> color_eyre::Result<Vec<PlaylistEntry>> {
+    match PlaylistFormat::from_path(path) {
+        Some(PlaylistFormat::M3u) => read_m3u(path),
+        Some(PlaylistFormat::Pls) => read_pls(path),
+        Some(PlaylistFormat::Xspf) => read_xspf(path),
+        None => Err(eyre!("Unsupported playlist format: {}", path.display())),
+    }
+}
+
+pub fn write(path: &Path, songs: &[Song]) -> color_eyre::Result<()> {
+    match PlaylistFormat::from_path(path) {
+        Some(PlaylistFormat::M3u) => write_m3u(path, songs),
+        Some(PlaylistFormat::Pls) => write_pls(path, songs),
+        Some(PlaylistFormat::Xspf) => write_xspf(path, songs),
+        None => Err(eyre!("Unsupported playlist format: {}", path.display())),
+    }
+}
+
+pub fn read_to_string(path: &Path) -> color_eyre::Result<String> {
+    let bytes = fs::read(path)?;
+
+    // Plain .m3u and .pls files are traditionally Latin-1, fall back to that
+    // if the file is not valid UTF-8.
+    Ok(match String::from_utf8(bytes) {
+        Ok(content) => content,
+        Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+    })
+}
+
+pub fn read_m3u(path: &Path) -> color_eyre::Result<Vec<PlaylistEntry>> {
+    let content = read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new("/"));
+
+    let mut entries: Vec<PlaylistEntry> = vec![];
+    let mut info: Option<(Option<String>, Option<String>)> = None;
+
+    for line in content.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            info = Some(parse_extinf(extinf));
+            continue;
+        }
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let (title, artist) = info.take().unwrap_or((None, None));
+
+        if let Some(location) = resolve_location(base, line) {
+            entries.push(PlaylistEntry::new(location, title, artist));
+        }
+    }
+
+    Ok(entries)
+}
+
+fn parse_extinf(extinf: &str) -> (Option<String>, Option<String>) {
+    // #EXTINF:<duration> [attributes],<artist> - <title>
+    match extinf.split_once(',') {
+        Some((_, display)) => split_display_title(display),
+        None => (None, None),
+    }
+}
+
+fn split_display_title(display: &str) -> (Option<String>, Option<String>) {
+    let display = display.trim();
+
+    if display.is_empty() {
+        return (None, None);
+    }
+
+    match display.split_once(" - ") {
+        Some((artist, title)) => (
+            Some(title.trim().to_string()),
+            Some(artist.trim().to_string()),
+        ),
+        None => (Some(display.to_string()), None),
+    }
+}
+
+pub fn write_m3u(path: &Path, songs: &[Song]) -> color_eyre::Result<()> {
+    let base = path.parent().unwrap_or(Path::new("/"));
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writeln!(writer, "#EXTM3U")?;
+
+    for song in songs {
+        if let Some(display) = display_title(song)? {
+            writeln!(writer, "#EXTINF:-1,{display}")?;
+        }
+
+        write_location(&mut writer, base, &song.get_path())?;
+        writeln!(writer)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the path as it is, or as a percent-encoded `file://` URI if it
+/// isn't UTF-8, so the file stays UTF-8 and the name survives a reload.
+fn write_location(writer: &mut impl Write, base: &Path, path: &Path) -> std::io::Result<()> {
+    match relative_location(base, path).to_str() {
+        Some(location) => write!(writer, "{location}"),
+        None => write!(writer, "file://{}", uri_encode(path.as_os_str().as_bytes())),
+    }
+}
+
+pub fn read_pls(path: &Path) -> color_eyre::Result<Vec<PlaylistEntry>> {
+    let content = read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new("/"));
+
+    // Entries are numbered FileN/TitleN pairs that may appear in any order.
+    let mut files: Vec<(u32, String)> = vec![];
+    let mut titles: Vec<(u32, String)> = vec![];
+
+    for line in content.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let key = key.trim().to_lowercase();
+
+        if let Some(index) = key.strip_prefix("file")
+            && let Ok(index) = index.parse::<u32>()
+        {
+            files.push((index, value.trim().to_string()));
+        } else if let Some(index) = key.strip_prefix("title")
+            && let Ok(index) = index.parse::<u32>()
+        {
+            titles.push((index, value.trim().to_string()));
+        }
+    }
+
+    files.sort_by_key(|file| file.0);
+
+    Ok(files
+        .into_iter()
+        .filter_map(|(index, file)| {
+            let (title, artist) = titles
+                .iter()
+                .find(|title| title.0 == index)
+                .map(|title| split_display_title(&title.1))
+                .unwrap_or((None, None));
+
+            resolve_location(base, &file)
+                .map(|location| PlaylistEntry::new(location, title, artist))
+        })
+        .collect())
+}
+
+pub fn write_pls(path: &Path, songs: &[Song]) -> color_eyre::Result<()> {
+    let base = path.parent().unwrap_or(Path::new("/"));
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writeln!(writer, "[playlist]")?;
+
+    for (i, song) in songs.iter().enumerate() {
+        let index = i + 1;
+
+        write!(writer, "File{index}=")?;
+        write_location(&mut writer, base, &song.get_path())?;
+        writeln!(writer)?;
+
+        if let Some(display) = display_title(song)? {
+            writeln!(writer, "Title{index}={display}")?;
+        }
+
+        writeln!(writer, "Length{index}=-1")?;
+    }
+
+    writeln!(writer, "NumberOfEntries={}", songs.len())?;
+    writeln!(writer, "Version=2")?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+pub fn read_xspf(path: &Path) -> color_eyre::Result<Vec<PlaylistEntry>> {
+    let content = read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new("/"));
+
+    if !content.contains("<playlist") {
+        return Err(eyre!("Not an XSPF playlist: {}", path.display()));
+    }
+
+    let mut entries: Vec<PlaylistEntry> = vec![];
+    let mut rest = content.as_str();
+
+    while let Some(track) = xml_element(rest, "track") {
+        rest = track.1;
+
+        let Some(location) = xml_element(track.0, "location") else {
+            continue;
+        };
+
+        let title = xml_element(track.0, "title").map(|title| xml_unescape(title.0));
+        let artist = xml_element(track.0, "creator").map(|artist| xml_unescape(artist.0));
+
+        if let Some(location) = resolve_location(base, &xml_unescape(location.0)) {
+            entries.push(PlaylistEntry::new(location, title, artist));
+        }
+    }
+
+    Ok(entries)
+}
+
+pub fn write_xspf(path: &Path, songs: &[Song]) -> color_eyre::Result<()> {
+    let base = path.parent().unwrap_or(Path::new("/"));
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<playlist version="1" xmlns="http://xspf.org/ns/0/">"#
+    )?;
+    writeln!(writer, "  <trackList>")?;
+
+    for song in songs {
+        let path = song.get_path();
+        let relative = relative_location(base, &path);
+
+        let location = if relative.is_absolute() {
+            format!("file://{}", uri_encode(relative.as_os_str().as_bytes()))
+        } else {
+            uri_encode(relative.as_os_str().as_bytes())
+        };
+
+        writeln!(writer, "    <track>")?;
+        writeln!(
+            writer,
+            "      <location>{}</location>",
+            xml_escape(&location)
+        )?;
+
+        if let Some(title) = song.get_title()? {
+            writeln!(writer, "      <title>{}</title>", xml_escape(&title))?;
+        }
+
+        if let Some(artist) = song.get_artist()? {
+            writeln!(writer, "      <creator>{}</creator>", xml_escape(&artist))?;
+        }
+
+        writeln!(writer, "    </track>")?;
+    }
+
+    writeln!(writer, "  </trackList>")?;
+    writeln!(writer, "</playlist>")?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Returns the inner text of the first `<name>` element in `xml` and the
+/// remainder of the input after its closing tag.
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let open = format!("<{name}");
+    let close = format!("</{name}>");
+
+    let mut search = xml;
+
+    loop {
+        let start = search.find(&open)?;
+        let after_name = &search[start + open.len()..];
+
+        // Make sure we matched `<track>` and not `<trackList>`.
+        if after_name.starts_with(['>', ' ', '\t', '\r', '\n', '/']) {
+            let tag_end = after_name.find('>')?;
+
+            if after_name[..tag_end].ends_with('/') {
+                return Some(("", &after_name[tag_end + 1..]));
+            }
+
+            let inner = &after_name[tag_end + 1..];
+            let end = inner.find(&close)?;
+
+            return Some((inner[..end].trim(), &inner[end + close.len()..]));
+        }
+
+        search = after_name;
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    let text = text.trim_start_matches("<![CDATA[").trim_end_matches("]]>");
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+
+        let entity = &rest[1..end];
+
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+fn uri_encode(path: &[u8]) -> String {
+    let mut encoded = String::with_capacity(path.len());
+
+    for &byte in path {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
+}
+
+/// Decodes to a path, the bytes don't need to be UTF-8.
+fn uri_decode(uri: &str) -> PathBuf {
+    let bytes = uri.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = uri.get(i + 1..i + 3)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    PathBuf::from(OsStr::from_bytes(&decoded))
+}
+
+/// Resolves a playlist location (absolute path, path relative to the playlist
+/// or `file://` URI) to a local path. Remote URLs are not supported.
+pub fn resolve_location(base: &Path, location: &str) -> Option<PathBuf> {
+    let location = location.trim();
+
+    let path = if let Some(uri) = location.strip_prefix("file://") {
+        // file:///path and file://localhost/path
+        let uri = uri.strip_prefix("localhost").unwrap_or(uri);
+        uri_decode(uri)
+    } else if location.contains("://") {
+        return None;
+    } else if location.contains('%') && !base.join(location).exists() {
+        // Relative URIs from XSPF files are percent-encoded.
+        uri_decode(&location.replace('\\', "/"))
+    } else {
+        PathBuf::from(location.replace('\\', "/"))
+    };
+
+    if path.is_absolute() {
+        Some(path)
+    } else {
+        Some(base.join(path))
+    }
+}
+
+fn display_title(song: &Song) -> color_eyre::Result<Option<String>> {
+    Ok(match (song.get_artist()?, song.get_title()?) {
+        (Some(artist), Some(title)) => Some(format!("{artist} - {title}")),
+        (None, Some(title)) => Some(title),
+        _ => None,
+    })
+}
+
+fn relative_location(base: &Path, path: &Path) -> PathBuf {
+    match path.strip_prefix(base) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, ffi::OsString, os::unix::ffi::OsStringExt};
+
+    #[test]
+    fn keeps_non_utf8_paths_through_a_reload() {
+        let dir = env::temp_dir().join(format!("playlist-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let paths = [
+            dir.join("Café.flac"),
+            dir.join(OsString::from_vec(b"Caf\xe9.flac".to_vec())),
+            dir.join("sub")
+                .join(OsString::from_vec(b"\xff 100%.flac".to_vec())),
+            PathBuf::from("/music/Ünïcode - Ärtist.flac"),
+        ];
+
+        let songs: Vec<Song> = paths
+            .iter()
+            .map(|path| {
+                let mut song = Song::new(path.clone());
+                song.set_title(String::from("Tïtle"));
+                song
+            })
+            .collect();
+
+        for format in [
+            PlaylistFormat::M3u,
+            PlaylistFormat::Pls,
+            PlaylistFormat::Xspf,
+        ] {
+            let playlist = dir.join(format!("queue.{}", format.extension()));
+            write(&playlist, &songs).unwrap();
+
+            assert!(String::from_utf8(fs::read(&playlist).unwrap()).is_ok());
+
+            let entries = read(&playlist).unwrap();
+            let read: Vec<PathBuf> = entries.iter().map(PlaylistEntry::get_path).collect();
+
+            assert_eq!(read, paths, "{}", format.extension());
+            assert!(entries
+                .iter()
+                .all(|entry| entry.get_title().as_deref() == Some("Tïtle")));
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
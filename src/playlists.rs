@@ -0,0 +1,619 @@
+use color_eyre::eyre::eyre;
+use crossterm::event::{KeyCode, KeyEvent};
+use std::{
+    collections::HashMap,
+    fs::{self, read_dir},
+    path::PathBuf,
+};
+
+use crate::{
+    browser::Browser,
+    format,
+    library::Library,
+    paths,
+    player_controller::PlayerController,
+    playlist::{self, PlaylistEntry},
+    query::Query,
+    song::Song,
+};
+
+#[derive(Clone, PartialEq)]
+pub enum InputAction {
+    Create,
+    CreateSmart,
+    Rename,
+    Rule(String),
+}
+
+pub struct Input {
+    action: InputAction,
+    text: String,
+}
+
+impl Input {
+    pub fn get_prompt(&self) -> &'static str {
+        match self.action {
+            InputAction::Create => "New playlist",
+            InputAction::CreateSmart => "New smart playlist",
+            InputAction::Rename => "Rename to",
+            InputAction::Rule(_) => "Rule",
+        }
+    }
+
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct PlaylistInfo {
+    name: String,
+    smart: bool,
+}
+
+impl PlaylistInfo {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Smart playlists are stored as a rule and generated from the library.
+    pub fn is_smart(&self) -> bool {
+        self.smart
+    }
+}
+
+/// Tracks of a single playlist opened for editing. Changes are written back
+/// to the playlist file, the live queue is never touched.
+pub struct Detail {
+    info: PlaylistInfo,
+    rule: Option<String>,
+    entries: Vec<PlaylistEntry>,
+    selected: u32,
+}
+
+impl Detail {
+    pub fn get_name(&self) -> &str {
+        &self.info.name
+    }
+
+    pub fn get_rule(&self) -> Option<&str> {
+        self.rule.as_deref()
+    }
+
+    pub fn get_entries(&self) -> &[PlaylistEntry] {
+        &self.entries
+    }
+
+    pub fn get_selected(&self) -> u32 {
+        self.selected
+    }
+}
+
+pub struct Playlists {
+    dir: PathBuf,
+    selected: u32,
+    detail: Option<Detail>,
+    input: Option<Input>,
+    status: Option<String>,
+    smart: HashMap<String, Vec<PlaylistEntry>>,
+    smart_generation: Option<u64>,
+}
+
+impl Playlists {
+    pub fn new() -> color_eyre::Result<Playlists> {
+        Ok(Playlists {
+            dir: paths::playlists_dir()?,
+            selected: 0,
+            detail: None,
+            input: None,
+            status: None,
+            smart: HashMap::new(),
+            smart_generation: None,
+        })
+    }
+
+    pub fn handle_key_event(
+        &mut self,
+        key_event: KeyEvent,
+        pc: &mut PlayerController,
+        browser: &Browser,
+    ) -> color_eyre::Result<()> {
+        self.status = None;
+
+        if self.input.is_some() {
+            return self.handle_input(key_event);
+        }
+
+        if self.detail.is_some() {
+            return self.handle_detail_key_event(key_event);
+        }
+
+        match key_event.code {
+            KeyCode::Char('h') => self.selected = 0,
+            KeyCode::Char('j') => self.select_next()?,
+            KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('l') => self.selected = self.list()?.len().saturating_sub(1) as u32,
+            KeyCode::Enter => self.open()?,
+            KeyCode::Char('n') => self.start_input(InputAction::Create, String::new()),
+            KeyCode::Char('N') => self.start_input(InputAction::CreateSmart, String::new()),
+            KeyCode::Char('R') => {
+                if let Some(info) = self.get_selected_info()? {
+                    self.start_input(InputAction::Rename, info.name)
+                }
+            }
+            KeyCode::Char('e') => self.edit_rule()?,
+            KeyCode::Char('d') => self.delete()?,
+            KeyCode::Char('y') => self.duplicate()?,
+            KeyCode::Char('a') => self.append_current_song(pc)?,
+            KeyCode::Char('A') => self.append_browser_item(browser)?,
+            KeyCode::Char('r') => self.replace_queue(pc)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_detail_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        let Some(detail) = &mut self.detail else {
+            return Ok(());
+        };
+
+        let len = detail.entries.len() as u32;
+        let selected = detail.selected;
+
+        if detail.info.smart && matches!(key_event.code, KeyCode::Char('J' | 'K' | 'd')) {
+            self.status = Some(String::from(
+                "Smart playlists follow their rule, press 'e' in the list to edit it.",
+            ));
+            return Ok(());
+        }
+
+        match key_event.code {
+            KeyCode::Char('h') => detail.selected = 0,
+            KeyCode::Char('j') if selected + 1 < len => detail.selected += 1,
+            KeyCode::Char('k') => detail.selected = selected.saturating_sub(1),
+            KeyCode::Char('l') => detail.selected = len.saturating_sub(1),
+            KeyCode::Char('J') if selected + 1 < len => {
+                detail
+                    .entries
+                    .swap(selected as usize, selected as usize + 1);
+                detail.selected += 1;
+                self.save_detail()?;
+            }
+            KeyCode::Char('K') if selected > 0 && selected < len => {
+                detail
+                    .entries
+                    .swap(selected as usize, selected as usize - 1);
+                detail.selected -= 1;
+                self.save_detail()?;
+            }
+            KeyCode::Char('d') if selected < len => {
+                detail.entries.remove(selected as usize);
+
+                if detail.selected > 0 && detail.selected + 1 >= len {
+                    detail.selected -= 1;
+                }
+
+                self.save_detail()?;
+            }
+            KeyCode::Backspace | KeyCode::Esc => self.detail = None,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_input(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
+        let Some(input) = &mut self.input else {
+            return Ok(());
+        };
+
+        match key_event.code {
+            KeyCode::Char(c) => input.text.push(c),
+            KeyCode::Backspace => {
+                input.text.pop();
+            }
+            KeyCode::Esc => self.input = None,
+            KeyCode::Enter => {
+                if let Some(input) = self.input.take() {
+                    self.submit_input(input)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn submit_input(&mut self, input: Input) -> color_eyre::Result<()> {
+        if let InputAction::Rule(name) = &input.action {
+            return self.submit_rule(name.clone(), input);
+        }
+
+        let name = input.text.trim().to_string();
+
+        if let Err(e) = validate_name(&name) {
+            self.status = Some(e.to_string());
+            self.input = Some(input);
+            return Ok(());
+        }
+
+        if self.name_taken(&name) {
+            self.status = Some(format!("Playlist \"{name}\" already exists."));
+            self.input = Some(input);
+            return Ok(());
+        }
+
+        match input.action {
+            InputAction::Create => {
+                playlist::write_m3u(&self.path_for(&name, false), &[])?;
+                self.select_name(&name)?;
+            }
+            InputAction::CreateSmart => {
+                self.start_input(InputAction::Rule(name), String::new());
+            }
+            InputAction::Rename => {
+                if let Some(info) = self.get_selected_info()? {
+                    fs::rename(
+                        self.path_for(&info.name, info.smart),
+                        self.path_for(&name, info.smart),
+                    )?;
+                    self.smart_generation = None;
+                    self.select_name(&name)?;
+                }
+            }
+            InputAction::Rule(_) => {}
+        }
+
+        Ok(())
+    }
+
+    fn submit_rule(&mut self, name: String, input: Input) -> color_eyre::Result<()> {
+        let rule = input.text.trim().to_string();
+
+        if let Err(e) = Query::parse(&rule) {
+            self.status = Some(format!("Invalid rule, {e}"));
+            self.input = Some(input);
+            return Ok(());
+        }
+
+        fs::write(self.path_for(&name, true), format!("{rule}\n"))?;
+
+        self.smart_generation = None;
+        self.select_name(&name)?;
+
+        Ok(())
+    }
+
+    /// Saves `songs` as a new playlist called `name`.
+    pub fn save(&mut self, name: &str, songs: &[Song]) -> color_eyre::Result<()> {
+        validate_name(name)?;
+
+        if self.name_taken(name) {
+            return Err(eyre!("Playlist \"{name}\" already exists."));
+        }
+
+        playlist::write_m3u(&self.path_for(name, false), songs)?;
+        self.select_name(name)?;
+
+        Ok(())
+    }
+
+    fn start_input(&mut self, action: InputAction, text: String) {
+        self.input = Some(Input { action, text });
+    }
+
+    fn edit_rule(&mut self) -> color_eyre::Result<()> {
+        match self.get_selected_info()? {
+            Some(info) if info.smart => {
+                let rule = self.read_rule(&info.name)?;
+                self.start_input(InputAction::Rule(info.name), rule);
+            }
+            Some(_) => {
+                self.status = Some(String::from("Only smart playlists have a rule."));
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn list(&self) -> color_eyre::Result<Vec<PlaylistInfo>> {
+        let mut playlists: Vec<PlaylistInfo> = read_dir(&self.dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let name = path.file_stem()?.to_str()?.to_string();
+
+                match path.extension()?.to_str()? {
+                    "m3u8" => Some(PlaylistInfo { name, smart: false }),
+                    "smart" => Some(PlaylistInfo { name, smart: true }),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        playlists.sort_by_key(|info| info.name.to_lowercase());
+
+        Ok(playlists)
+    }
+
+    fn path_for(&self, name: &str, smart: bool) -> PathBuf {
+        if smart {
+            self.dir.join(format!("{name}.smart"))
+        } else {
+            self.dir.join(format!("{name}.m3u8"))
+        }
+    }
+
+    fn name_taken(&self, name: &str) -> bool {
+        self.path_for(name, false).exists() || self.path_for(name, true).exists()
+    }
+
+    fn read_rule(&self, name: &str) -> color_eyre::Result<String> {
+        Ok(fs::read_to_string(self.path_for(name, true))?
+            .trim()
+            .to_string())
+    }
+
+    pub fn get_selected_info(&self) -> color_eyre::Result<Option<PlaylistInfo>> {
+        Ok(self.list()?.get(self.selected as usize).cloned())
+    }
+
+    fn select_name(&mut self, name: &str) -> color_eyre::Result<()> {
+        if let Some(i) = self.list()?.iter().position(|info| info.name == name) {
+            self.selected = i as u32;
+        }
+
+        Ok(())
+    }
+
+    fn select_next(&mut self) -> color_eyre::Result<()> {
+        if self.selected + 1 < self.list()?.len() as u32 {
+            self.selected += 1;
+        }
+
+        Ok(())
+    }
+
+    fn entries_for(&self, info: &PlaylistInfo) -> color_eyre::Result<Vec<PlaylistEntry>> {
+        if info.smart {
+            Ok(self.smart.get(&info.name).cloned().unwrap_or_default())
+        } else {
+            playlist::read_m3u(&self.path_for(&info.name, false))
+        }
+    }
+
+    fn write(&self, name: &str, entries: &[PlaylistEntry]) -> color_eyre::Result<()> {
+        let songs: Vec<Song> = entries
+            .iter()
+            .cloned()
+            .map(|entry| entry.into_song())
+            .collect();
+
+        playlist::write_m3u(&self.path_for(name, false), &songs)
+    }
+
+    fn save_detail(&self) -> color_eyre::Result<()> {
+        if let Some(detail) = &self.detail {
+            self.write(&detail.info.name, &detail.entries)?;
+        }
+
+        Ok(())
+    }
+
+    fn open(&mut self) -> color_eyre::Result<()> {
+        if let Some(info) = self.get_selected_info()? {
+            let rule = if info.smart {
+                Some(self.read_rule(&info.name)?)
+            } else {
+                None
+            };
+
+            self.detail = Some(Detail {
+                entries: self.entries_for(&info)?,
+                info,
+                rule,
+                selected: 0,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn delete(&mut self) -> color_eyre::Result<()> {
+        if let Some(info) = self.get_selected_info()? {
+            fs::remove_file(self.path_for(&info.name, info.smart))?;
+
+            if self.selected > 0 && self.selected >= self.list()?.len() as u32 {
+                self.selected -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn duplicate(&mut self) -> color_eyre::Result<()> {
+        if let Some(info) = self.get_selected_info()? {
+            let mut copy = format!("{} (copy)", info.name);
+            let mut i = 2;
+
+            while self.name_taken(&copy) {
+                copy = format!("{} (copy {i})", info.name);
+                i += 1;
+            }
+
+            fs::copy(
+                self.path_for(&info.name, info.smart),
+                self.path_for(&copy, info.smart),
+            )?;
+            self.smart_generation = None;
+            self.select_name(&copy)?;
+        }
+
+        Ok(())
+    }
+
+    fn append(&mut self, new_entries: Vec<PlaylistEntry>) -> color_eyre::Result<()> {
+        if new_entries.is_empty() {
+            return Ok(());
+        }
+
+        match self.get_selected_info()? {
+            Some(info) if info.smart => {
+                self.status = Some(String::from(
+                    "Smart playlists can't be edited by hand, change the rule instead.",
+                ));
+            }
+            Some(info) => {
+                let mut entries = self.entries_for(&info)?;
+                let count = new_entries.len();
+                entries.extend(new_entries);
+                self.write(&info.name, &entries)?;
+                self.status = Some(format!("Added {count} track(s) to \"{}\".", info.name));
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn append_current_song(&mut self, pc: &PlayerController) -> color_eyre::Result<()> {
+        if let Some(song) = pc.queue.get_current_song()? {
+            self.append(vec![PlaylistEntry::new(
+                song.get_path(),
+                song.get_title()?,
+                song.get_artist()?,
+            )])?;
+        }
+
+        Ok(())
+    }
+
+    fn append_browser_item(&mut self, browser: &Browser) -> color_eyre::Result<()> {
+        let path = browser.get_selected_full_path()?;
+
+        let entries = if path.is_dir() {
+            let mut files: Vec<PathBuf> = read_dir(&path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|entry| format::is_supported(entry))
+                .collect();
+
+            files.sort();
+
+            files
+                .into_iter()
+                .map(|file| PlaylistEntry::new(file, None, None))
+                .collect()
+        } else if playlist::is_playlist(&path) {
+            playlist::read(&path)?
+        } else if format::is_supported(&path) {
+            vec![PlaylistEntry::new(path, None, None)]
+        } else {
+            vec![]
+        };
+
+        self.append(entries)
+    }
+
+    fn replace_queue(&mut self, pc: &mut PlayerController) -> color_eyre::Result<()> {
+        if let Some(info) = self.get_selected_info()? {
+            pc.load_playlist(self.entries_for(&info)?, true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-evaluates the smart playlists whenever the library index or the
+    /// play statistics changed.
+    pub fn update(&mut self, library: &Library) -> color_eyre::Result<()> {
+        if self.smart_generation == Some(library.get_generation()) {
+            return Ok(());
+        }
+
+        self.smart_generation = Some(library.get_generation());
+        self.smart.clear();
+
+        for info in self.list()?.into_iter().filter(|info| info.smart) {
+            // Rules are validated when they are saved, a rule that was broken
+            // by editing the file by hand simply matches nothing.
+            let entries = match Query::parse(&self.read_rule(&info.name)?) {
+                Ok(query) => library
+                    .get_tracks()
+                    .iter()
+                    .filter(|track| query.matches(track, library.get_play_count(&track.get_path())))
+                    .map(|track| {
+                        PlaylistEntry::new(track.get_path(), track.get_title(), track.get_artist())
+                    })
+                    .collect(),
+                Err(_) => vec![],
+            };
+
+            self.smart.insert(info.name, entries);
+        }
+
+        if let Some(detail) = &mut self.detail
+            && detail.info.smart
+        {
+            detail.entries = self
+                .smart
+                .get(&detail.info.name)
+                .cloned()
+                .unwrap_or_default();
+            detail.selected = detail
+                .selected
+                .min(detail.entries.len().saturating_sub(1) as u32);
+        }
+
+        Ok(())
+    }
+
+    pub fn get_selected(&self) -> color_eyre::Result<u32> {
+        Ok(self.selected)
+    }
+
+    /// Selects an entry of the open playlist, or a playlist when none is
+    /// open.
+    pub fn set_selected(&mut self, selected: usize) -> color_eyre::Result<()> {
+        match &mut self.detail {
+            Some(detail) => {
+                detail.selected =
+                    (selected as u32).min(detail.entries.len().saturating_sub(1) as u32)
+            }
+            None => {
+                self.selected = (selected as u32).min(self.list()?.len().saturating_sub(1) as u32)
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_detail(&self) -> Option<&Detail> {
+        self.detail.as_ref()
+    }
+
+    pub fn get_input(&self) -> Option<&Input> {
+        self.input.as_ref()
+    }
+
+    pub fn get_status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.input.is_some()
+    }
+}
+
+fn validate_name(name: &str) -> color_eyre::Result<()> {
+    if name.is_empty() {
+        return Err(eyre!("Playlist name can't be empty."));
+    }
+
+    if name.starts_with('.') || name.contains('/') || name.contains('\\') {
+        return Err(eyre!(
+            "Playlist name can't start with '.' or contain slashes."
+        ));
+    }
+
+    Ok(())
+}
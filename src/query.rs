@@ -0,0 +1,568 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use crate::library::TrackInfo;
+
+/// A parsed smart playlist rule, e.g.
+/// `genre:jazz AND year>=1960 AND NOT artist:"Kenny G"` or
+/// `playcount=0 added<30d`. Conditions next to each other are joined with
+/// an implicit AND.
+#[derive(Clone, Debug)]
+pub enum Query {
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Condition(Condition),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Track,
+    PlayCount,
+    Added,
+    Path,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Contains,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Text(String),
+    Number(i64),
+    Age(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub struct Condition {
+    field: Field,
+    operator: Operator,
+    value: Value,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    position: usize,
+    message: String,
+}
+
+impl ParseError {
+    fn new(position: usize, message: impl Into<String>) -> ParseError {
+        ParseError {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.position + 1, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Field> {
+        Some(match name.to_lowercase().as_str() {
+            "title" => Field::Title,
+            "artist" => Field::Artist,
+            "album" => Field::Album,
+            "genre" => Field::Genre,
+            "year" => Field::Year,
+            "track" => Field::Track,
+            "playcount" | "plays" => Field::PlayCount,
+            "added" => Field::Added,
+            "path" => Field::Path,
+            _ => return None,
+        })
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Field::Year | Field::Track | Field::PlayCount)
+    }
+}
+
+const FIELD_NAMES: &str = "title, artist, album, genre, year, track, playcount, added, path";
+
+impl Operator {
+    fn from_symbol(symbol: &str) -> Option<Operator> {
+        Some(match symbol {
+            ":" => Operator::Contains,
+            "=" | "==" => Operator::Equal,
+            "!=" => Operator::NotEqual,
+            "<" => Operator::Less,
+            "<=" => Operator::LessEqual,
+            ">" => Operator::Greater,
+            ">=" => Operator::GreaterEqual,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Operator(String),
+    LeftParen,
+    RightParen,
+}
+
+fn is_operator_char(c: char) -> bool {
+    matches!(c, ':' | '=' | '!' | '<' | '>')
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            tokens.push((start, Token::LeftParen));
+        } else if c == ')' {
+            chars.next();
+            tokens.push((start, Token::RightParen));
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            let mut closed = false;
+
+            while let Some((_, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    '\\' => {
+                        if let Some((_, escaped)) = chars.next() {
+                            text.push(escaped);
+                        }
+                    }
+                    _ => text.push(c),
+                }
+            }
+
+            if !closed {
+                return Err(ParseError::new(start, "unterminated quoted string"));
+            }
+
+            tokens.push((start, Token::Text(text)));
+        } else if is_operator_char(c) {
+            let mut operator = String::new();
+
+            while let Some(&(_, c)) = chars.peek()
+                && is_operator_char(c)
+            {
+                operator.push(c);
+                chars.next();
+            }
+
+            tokens.push((start, Token::Operator(operator)));
+        } else {
+            let mut word = String::new();
+
+            while let Some(&(_, c)) = chars.peek()
+                && !c.is_whitespace()
+                && !is_operator_char(c)
+                && !matches!(c, '(' | ')' | '"')
+            {
+                word.push(c);
+                chars.next();
+            }
+
+            tokens.push((start, Token::Word(word)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|token| &token.1)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.0)
+            .unwrap_or(self.end)
+    }
+
+    fn next(&mut self) -> Option<(usize, Token)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_or(&mut self) -> Result<Query, ParseError> {
+        let mut query = self.parse_and()?;
+
+        while self.peek_keyword("or") {
+            self.next();
+            query = Query::Or(Box::new(query), Box::new(self.parse_and()?));
+        }
+
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, ParseError> {
+        let mut query = self.parse_not()?;
+
+        loop {
+            if self.peek_keyword("and") {
+                self.next();
+            } else if self.peek().is_none()
+                || self.peek_keyword("or")
+                || self.peek() == Some(&Token::RightParen)
+            {
+                break;
+            }
+
+            query = Query::And(Box::new(query), Box::new(self.parse_not()?));
+        }
+
+        Ok(query)
+    }
+
+    fn parse_not(&mut self) -> Result<Query, ParseError> {
+        if self.peek_keyword("not") {
+            self.next();
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, ParseError> {
+        let offset = self.offset();
+
+        match self.next() {
+            Some((_, Token::LeftParen)) => {
+                let query = self.parse_or()?;
+
+                match self.next() {
+                    Some((_, Token::RightParen)) => Ok(query),
+                    _ => Err(ParseError::new(offset, "unmatched '('")),
+                }
+            }
+            Some((_, Token::Word(word))) => self.parse_condition(offset, &word),
+            Some((_, Token::RightParen)) => Err(ParseError::new(offset, "unexpected ')'")),
+            Some((_, Token::Operator(operator))) => Err(ParseError::new(
+                offset,
+                format!("expected a field before '{operator}'"),
+            )),
+            Some((_, Token::Text(_))) => Err(ParseError::new(
+                offset,
+                "expected a field before the quoted value",
+            )),
+            None => Err(ParseError::new(offset, "expected a condition")),
+        }
+    }
+
+    fn parse_condition(&mut self, offset: usize, name: &str) -> Result<Query, ParseError> {
+        let field = Field::from_name(name).ok_or_else(|| {
+            ParseError::new(
+                offset,
+                format!("unknown field '{name}', expected one of: {FIELD_NAMES}"),
+            )
+        })?;
+
+        let operator_offset = self.offset();
+
+        let operator = match self.next() {
+            Some((_, Token::Operator(symbol))) => {
+                Operator::from_symbol(&symbol).ok_or_else(|| {
+                    ParseError::new(operator_offset, format!("unknown operator '{symbol}'"))
+                })?
+            }
+            _ => {
+                return Err(ParseError::new(
+                    operator_offset,
+                    format!("expected an operator after '{name}'"),
+                ));
+            }
+        };
+
+        let value_offset = self.offset();
+
+        let text = match self.next() {
+            Some((_, Token::Word(word))) => word,
+            Some((_, Token::Text(text))) => text,
+            _ => {
+                return Err(ParseError::new(
+                    value_offset,
+                    format!("expected a value after '{name}'"),
+                ));
+            }
+        };
+
+        let value = if field == Field::Added {
+            Value::Age(parse_age(&text).ok_or_else(|| {
+                ParseError::new(
+                    value_offset,
+                    format!("expected an age like 12h, 30d, 2w or 1y, found '{text}'"),
+                )
+            })?)
+        } else if field.is_numeric() {
+            Value::Number(text.parse().map_err(|_| {
+                ParseError::new(value_offset, format!("expected a number, found '{text}'"))
+            })?)
+        } else {
+            if !matches!(
+                operator,
+                Operator::Contains | Operator::Equal | Operator::NotEqual
+            ) {
+                return Err(ParseError::new(
+                    operator_offset,
+                    format!("'{name}' only supports ':', '=' and '!='"),
+                ));
+            }
+
+            Value::Text(text.to_lowercase())
+        };
+
+        Ok(Query::Condition(Condition {
+            field,
+            operator,
+            value,
+        }))
+    }
+}
+
+fn parse_age(text: &str) -> Option<Duration> {
+    let split = text.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = text.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+
+    let seconds = match unit {
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "m" => 30 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(amount.checked_mul(seconds)?))
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+            end: input.len(),
+        };
+
+        let query = parser.parse_or()?;
+
+        if parser.peek().is_some() {
+            return Err(ParseError::new(parser.offset(), "unexpected ')'"));
+        }
+
+        Ok(query)
+    }
+
+    pub fn matches(&self, track: &TrackInfo, play_count: u32) -> bool {
+        match self {
+            Query::And(a, b) => a.matches(track, play_count) && b.matches(track, play_count),
+            Query::Or(a, b) => a.matches(track, play_count) || b.matches(track, play_count),
+            Query::Not(query) => !query.matches(track, play_count),
+            Query::Condition(condition) => condition.matches(track, play_count),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, track: &TrackInfo, play_count: u32) -> bool {
+        match &self.value {
+            Value::Text(value) => {
+                let field = match self.field {
+                    Field::Title => track.get_title(),
+                    Field::Artist => track.get_artist(),
+                    Field::Album => track.get_album(),
+                    Field::Genre => track.get_genre(),
+                    Field::Path => Some(track.get_path().to_string_lossy().into_owned()),
+                    _ => None,
+                };
+
+                let Some(field) = field.map(|field| field.to_lowercase()) else {
+                    return self.operator == Operator::NotEqual;
+                };
+
+                match self.operator {
+                    Operator::Contains => field.contains(value.as_str()),
+                    Operator::Equal => field == *value,
+                    Operator::NotEqual => field != *value,
+                    _ => false,
+                }
+            }
+            Value::Number(value) => {
+                let field = match self.field {
+                    Field::Year => track.get_year().map(i64::from),
+                    Field::Track => track.get_track_number().map(i64::from),
+                    Field::PlayCount => Some(i64::from(play_count)),
+                    _ => None,
+                };
+
+                field.is_some_and(|field| compare(self.operator, field, *value))
+            }
+            Value::Age(value) => {
+                let Some(age) = track
+                    .get_added()
+                    .and_then(|added| SystemTime::now().duration_since(added).ok())
+                else {
+                    return false;
+                };
+
+                // `added<30d` reads as "added less than 30 days ago".
+                compare(self.operator, age, *value)
+            }
+        }
+    }
+}
+
+fn compare<T: PartialOrd>(operator: Operator, field: T, value: T) -> bool {
+    match operator {
+        Operator::Contains | Operator::Equal => field == value,
+        Operator::NotEqual => field != value,
+        Operator::Less => field < value,
+        Operator::LessEqual => field <= value,
+        Operator::Greater => field > value,
+        Operator::GreaterEqual => field >= value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The query with explicit grouping, to compare against.
+    fn show(query: &Query) -> String {
+        match query {
+            Query::And(a, b) => format!("({} AND {})", show(a), show(b)),
+            Query::Or(a, b) => format!("({} OR {})", show(a), show(b)),
+            Query::Not(query) => format!("NOT {}", show(query)),
+            Query::Condition(condition) => format!(
+                "{:?} {:?} {:?}",
+                condition.field, condition.operator, condition.value
+            ),
+        }
+    }
+
+    fn parse(input: &str) -> String {
+        show(&Query::parse(input).unwrap())
+    }
+
+    fn error(input: &str) -> String {
+        Query::parse(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_conditions() {
+        assert_eq!(parse("genre:jazz"), r#"Genre Contains Text("jazz")"#);
+        assert_eq!(parse("year>=1960"), "Year GreaterEqual Number(1960)");
+        assert_eq!(parse("plays == 0"), "PlayCount Equal Number(0)");
+        assert_eq!(
+            parse(r#"artist!="Kenny \"G\"""#),
+            r#"Artist NotEqual Text("kenny \"g\"")"#
+        );
+        assert_eq!(parse("added<2w"), "Added Less Age(1209600s)");
+    }
+
+    #[test]
+    fn parses_operators_by_precedence() {
+        assert_eq!(
+            parse("genre:jazz AND year>=1960 AND NOT artist:\"Kenny G\""),
+            parse("genre:jazz year>=1960 not artist:\"Kenny G\""),
+        );
+        assert_eq!(
+            parse("genre:jazz OR genre:blues year<1970"),
+            r#"(Genre Contains Text("jazz") OR (Genre Contains Text("blues") AND Year Less Number(1970)))"#
+        );
+        assert_eq!(
+            parse("(genre:jazz or genre:blues) year<1970"),
+            r#"((Genre Contains Text("jazz") OR Genre Contains Text("blues")) AND Year Less Number(1970))"#
+        );
+        assert_eq!(
+            parse("not not plays=0"),
+            "NOT NOT PlayCount Equal Number(0)"
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_their_column() {
+        assert_eq!(error(""), "column 1: expected a condition");
+        assert_eq!(
+            error("mood:happy"),
+            format!("column 1: unknown field 'mood', expected one of: {FIELD_NAMES}")
+        );
+        assert_eq!(
+            error("genre jazz"),
+            "column 7: expected an operator after 'genre'"
+        );
+        assert_eq!(error("genre=<jazz"), "column 6: unknown operator '=<'");
+        assert_eq!(error("genre:"), "column 7: expected a value after 'genre'");
+        assert_eq!(
+            error("title>b"),
+            "column 6: 'title' only supports ':', '=' and '!='"
+        );
+        assert_eq!(
+            error("year>old"),
+            "column 6: expected a number, found 'old'"
+        );
+        assert_eq!(
+            error("added<30"),
+            "column 7: expected an age like 12h, 30d, 2w or 1y, found '30'"
+        );
+        assert_eq!(
+            error("artist:\"Kenny"),
+            "column 8: unterminated quoted string"
+        );
+        assert_eq!(error("(plays=0"), "column 1: unmatched '('");
+        assert_eq!(error("plays=0)"), "column 8: unexpected ')'");
+        assert_eq!(error("=0"), "column 1: expected a field before '='");
+    }
+
+    #[test]
+    fn rejects_ages_that_overflow() {
+        assert_eq!(
+            parse_age("30d"),
+            Some(Duration::from_secs(30 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_age("1000000000000y"), None);
+        assert_eq!(parse_age(&format!("{}h", u64::MAX)), None);
+        assert!(error("added>1000000000000y").starts_with("column 7: expected an age"));
+    }
+}
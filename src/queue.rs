@@ -1,15 +1,18 @@
 use crate::song::Song;
-use std::collections::VecDeque;
+use std::{collections::VecDeque, ops::Range};
 
 #[derive(Clone)]
 pub struct Queue {
     song_queue: VecDeque<Song>,
+    /// Entry highlighted in the queue window.
+    selected: usize,
 }
 
 impl Queue {
     pub fn new() -> Queue {
         Queue {
             song_queue: VecDeque::new(),
+            selected: 0,
         }
     }
 
@@ -17,8 +20,48 @@ impl Queue {
         self.song_queue.push_back(song);
     }
 
+    /// Inserts `song` anywhere after the current song.
+    pub fn insert_randomly(&mut self, song: Song) {
+        let index = match self.song_queue.len() {
+            0 => 0,
+            len => fastrand::usize(1..=len),
+        };
+
+        self.song_queue.insert(index, song);
+    }
+
+    /// Shuffles every song after the current one.
+    pub fn shuffle(&mut self) {
+        if let Some((_, upcoming)) = self.song_queue.make_contiguous().split_first_mut() {
+            fastrand::shuffle(upcoming);
+        }
+    }
+
     pub fn pop(&mut self) {
         self.song_queue.pop_front();
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Removes songs without playing them.
+    pub fn remove(&mut self, range: Range<usize>) {
+        let range = range.start.min(self.song_queue.len())..range.end.min(self.song_queue.len());
+        let removed = range.len();
+
+        if self.selected >= range.end {
+            self.selected -= removed;
+        } else if self.selected >= range.start {
+            self.selected = range.start;
+        }
+
+        self.song_queue.drain(range);
+    }
+
+    pub fn get_selected(&self) -> usize {
+        self.selected.min(self.song_queue.len().saturating_sub(1))
+    }
+
+    pub fn set_selected(&mut self, selected: usize) {
+        self.selected = selected.min(self.song_queue.len().saturating_sub(1));
     }
 
     pub fn get_queue(&self) -> color_eyre::Result<Vec<Song>> {
@@ -33,7 +76,12 @@ impl Queue {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.song_queue.is_empty()
+    }
+
     pub fn clear(&mut self) {
         self.song_queue.clear();
+        self.selected = 0;
     }
 }
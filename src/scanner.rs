@@ -0,0 +1,461 @@
+use color_eyre::eyre::eyre;
+use ebur128::{EbuR128, Mode};
+use id3::TagLike;
+use mp4ameta::{Data, FreeformIdent};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, TryRecvError},
+        Mutex,
+    },
+    thread,
+    time::SystemTime,
+};
+
+use crate::{
+    format::{self, ReplayGain},
+    library, paths,
+};
+
+/// ReplayGain 2.0 reference loudness in LUFS.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Frames handed to the loudness meter at once.
+const CHUNK_FRAMES: usize = 4096;
+
+/// The ReplayGain cache, loaded once and shared by the player, the Now
+/// Playing window and scans. Loaded again when another process, like a
+/// scan from the command line, changed the file.
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+struct Cache {
+    /// When the file was modified as it was loaded.
+    modified: Option<SystemTime>,
+    gains: HashMap<PathBuf, ReplayGain>,
+}
+
+pub enum ScanEvent {
+    Scanning {
+        index: usize,
+        total: usize,
+        path: PathBuf,
+    },
+    Finished(ScanSummary),
+    /// The scan stopped early, e.g. because the cache couldn't be written.
+    Failed(String),
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ScanSummary {
+    pub scanned: usize,
+    pub failed: usize,
+    /// Files whose tags were updated, only counted when writing.
+    pub written: usize,
+}
+
+struct Measurement {
+    path: PathBuf,
+    meter: EbuR128,
+    loudness: f64,
+    peak: f64,
+}
+
+/// Measures the integrated loudness and true peak of every supported file
+/// below `paths` and computes track and album gains. Files in one directory
+/// sharing an album tag form an album. Results always go to the ReplayGain
+/// cache, with `write` they are also written into the files' tags.
+pub fn scan(
+    paths: &[PathBuf],
+    write: bool,
+    mut progress: impl FnMut(ScanEvent),
+) -> color_eyre::Result<ScanSummary> {
+    let mut files = vec![];
+
+    for path in paths {
+        if path.is_dir() {
+            let mut found = vec![];
+            library::collect_songs(path, &mut found);
+            files.extend(found.into_iter().map(|(path, _)| path));
+        } else if format::is_supported(path) {
+            files.push(path.clone());
+        }
+    }
+
+    files.sort();
+    files.dedup();
+
+    let mut summary = ScanSummary::default();
+    let mut albums: HashMap<(PathBuf, Option<String>), Vec<Measurement>> = HashMap::new();
+
+    for (index, path) in files.iter().enumerate() {
+        progress(ScanEvent::Scanning {
+            index,
+            total: files.len(),
+            path: path.clone(),
+        });
+
+        match measure(path) {
+            Ok(measurement) => {
+                let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                let album = format::read_tags(path).ok().and_then(|tags| tags.album);
+
+                albums.entry((dir, album)).or_default().push(measurement);
+                summary.scanned += 1;
+            }
+            Err(_) => summary.failed += 1,
+        }
+    }
+
+    let mut measured = HashMap::new();
+
+    for tracks in albums.values() {
+        let album_loudness =
+            EbuR128::loudness_global_multiple(tracks.iter().map(|track| &track.meter))
+                .map_err(|e| eyre!("Failed to compute album loudness. {e}"))?;
+        let album_gain =
+            Some((REFERENCE_LOUDNESS - album_loudness) as f32).filter(|gain| gain.is_finite());
+        let album_peak = tracks.iter().map(|track| track.peak).fold(0.0, f64::max);
+
+        for track in tracks {
+            let gain = ReplayGain {
+                track_gain: Some((REFERENCE_LOUDNESS - track.loudness) as f32),
+                track_peak: Some(track.peak as f32),
+                album_gain,
+                album_peak: Some(album_peak as f32),
+            };
+
+            if write && write_tags(&track.path, &gain).is_ok() {
+                summary.written += 1;
+            }
+
+            measured.insert(track.path.clone(), gain);
+        }
+    }
+
+    update_cache(measured)?;
+
+    progress(ScanEvent::Finished(summary));
+
+    Ok(summary)
+}
+
+fn measure(path: &Path) -> color_eyre::Result<Measurement> {
+    let mut source = format::open(path)?;
+
+    let channels = source.channels() as usize;
+    let mut meter = EbuR128::new(
+        channels as u32,
+        source.sample_rate(),
+        Mode::I | Mode::TRUE_PEAK,
+    )
+    .map_err(|e| eyre!("Failed to create loudness meter. {e}"))?;
+
+    let mut buffer = Vec::with_capacity(CHUNK_FRAMES * channels);
+
+    loop {
+        buffer.clear();
+        buffer.extend(source.by_ref().take(CHUNK_FRAMES * channels));
+
+        // Drop a trailing partial frame.
+        buffer.truncate(buffer.len() / channels * channels);
+
+        if buffer.is_empty() {
+            break;
+        }
+
+        meter
+            .add_frames_f32(&buffer)
+            .map_err(|e| eyre!("Failed to measure {}. {e}", path.display()))?;
+    }
+
+    let loudness = meter
+        .loudness_global()
+        .map_err(|e| eyre!("Failed to measure {}. {e}", path.display()))?;
+
+    // Silence, or a file too short for a single gating block, has no
+    // loudness to correct.
+    if !loudness.is_finite() {
+        return Err(eyre!("Can't measure the loudness of {}.", path.display()));
+    }
+
+    let mut peak: f64 = 0.0;
+
+    for channel in 0..channels as u32 {
+        peak = peak.max(meter.true_peak(channel).unwrap_or(0.0));
+    }
+
+    Ok(Measurement {
+        path: path.to_path_buf(),
+        meter,
+        loudness,
+        peak,
+    })
+}
+
+fn tag_values(gain: &ReplayGain) -> Vec<(&'static str, String)> {
+    let mut values = vec![];
+
+    if let Some(track_gain) = gain.track_gain {
+        values.push(("REPLAYGAIN_TRACK_GAIN", format!("{track_gain:.2} dB")));
+    }
+
+    if let Some(track_peak) = gain.track_peak {
+        values.push(("REPLAYGAIN_TRACK_PEAK", format!("{track_peak:.6}")));
+    }
+
+    if let Some(album_gain) = gain.album_gain {
+        values.push(("REPLAYGAIN_ALBUM_GAIN", format!("{album_gain:.2} dB")));
+    }
+
+    if let Some(album_peak) = gain.album_peak {
+        values.push(("REPLAYGAIN_ALBUM_PEAK", format!("{album_peak:.6}")));
+    }
+
+    values
+}
+
+/// Writes the ReplayGain tags into MP3, FLAC and MPEG-4 files, other
+/// formats only get the cache entry.
+fn write_tags(path: &Path, gain: &ReplayGain) -> color_eyre::Result<()> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .unwrap_or_default();
+
+    match ext.as_str() {
+        "mp3" => {
+            let mut tag = id3::Tag::read_from_path(path).unwrap_or_default();
+
+            for (key, value) in tag_values(gain) {
+                tag.add_frame(id3::frame::ExtendedText {
+                    description: key.to_string(),
+                    value,
+                });
+            }
+
+            tag.write_to_path(path, id3::Version::Id3v24)?;
+        }
+        "flac" => {
+            let mut tag = metaflac::Tag::read_from_path(path)?;
+
+            for (key, value) in tag_values(gain) {
+                tag.set_vorbis(key, vec![value]);
+            }
+
+            tag.save()?;
+        }
+        "m4a" | "m4b" | "mp4" => {
+            let mut tag = mp4ameta::Tag::read_from_path(path)?;
+
+            for (key, value) in tag_values(gain) {
+                tag.set_data(
+                    FreeformIdent::new("com.apple.iTunes", key),
+                    Data::Utf8(value),
+                );
+            }
+
+            tag.write_to_path(path)?;
+        }
+        _ => return Err(eyre!("Can't write tags to {}", path.display())),
+    }
+
+    Ok(())
+}
+
+fn cache_path() -> color_eyre::Result<PathBuf> {
+    let mut path = paths::data_dir()?;
+    path.push("replaygain.tsv");
+    Ok(path)
+}
+
+fn load_cache() -> color_eyre::Result<HashMap<PathBuf, ReplayGain>> {
+    let path = cache_path()?;
+
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| {
+            // track gain, track peak, album gain, album peak, path
+            let mut fields = line.splitn(5, '\t');
+            let mut next = || fields.next().map(|field| field.parse().ok());
+
+            let gain = ReplayGain {
+                track_gain: next()?,
+                track_peak: next()?,
+                album_gain: next()?,
+                album_peak: next()?,
+            };
+
+            Some((paths::unescape(fields.next()?), gain))
+        })
+        .collect())
+}
+
+fn cache_modified() -> Option<SystemTime> {
+    fs::metadata(cache_path().ok()?).ok()?.modified().ok()
+}
+
+/// Adds `measured` to the entries on disk, which may have changed since
+/// they were loaded, and writes them back.
+fn update_cache(measured: HashMap<PathBuf, ReplayGain>) -> color_eyre::Result<()> {
+    // Held while writing, so two scans of this process don't lose each
+    // other's entries.
+    let mut cache = CACHE
+        .lock()
+        .map_err(|_| eyre!("ReplayGain cache is poisoned."))?;
+
+    let modified = cache_modified();
+    let mut gains = load_cache()?;
+    gains.extend(measured);
+
+    save_cache(&gains)?;
+
+    *cache = Some(Cache {
+        modified: cache_modified().or(modified),
+        gains,
+    });
+
+    Ok(())
+}
+
+/// Written to a temporary file first, so a reader never sees half of it.
+fn save_cache(cache: &HashMap<PathBuf, ReplayGain>) -> color_eyre::Result<()> {
+    let path = cache_path()?;
+    let temporary = path.with_extension("tsv.tmp");
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    let field = |value: Option<f32>| value.map(|v| v.to_string()).unwrap_or_default();
+
+    for (path, gain) in cache {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            field(gain.track_gain),
+            field(gain.track_peak),
+            field(gain.album_gain),
+            field(gain.album_peak),
+            paths::escape(path)
+        )?;
+    }
+
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(temporary, path)?;
+
+    Ok(())
+}
+
+/// Gains measured by an earlier scan, for files without ReplayGain tags.
+pub fn cached_gain(path: &Path) -> Option<ReplayGain> {
+    let mut cache = CACHE.lock().ok()?;
+    let modified = cache_modified();
+
+    if cache
+        .as_ref()
+        .is_none_or(|cache| cache.modified != modified)
+    {
+        *cache = Some(Cache {
+            modified,
+            gains: load_cache().ok()?,
+        });
+    }
+
+    cache.as_ref()?.gains.get(path).copied()
+}
+
+/// A scan running in the background, started from the browser.
+pub struct ScanJob {
+    receiver: mpsc::Receiver<ScanEvent>,
+    status: String,
+    finished: bool,
+    /// Why the scan failed, until it is taken.
+    error: Option<String>,
+}
+
+impl ScanJob {
+    pub fn start(path: PathBuf, write: bool) -> ScanJob {
+        let (tx, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            let result = scan(&[path], write, |event| {
+                let _ = tx.send(event);
+            });
+
+            if let Err(e) = result {
+                let _ = tx.send(ScanEvent::Failed(e.to_string()));
+            }
+        });
+
+        ScanJob {
+            receiver,
+            status: String::from("Scanning"),
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Picks up the progress made since the last call.
+    /// Returns whether the status changed.
+    pub fn update(&mut self) -> bool {
+        let mut changed = false;
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(ScanEvent::Scanning { index, total, .. }) => {
+                    self.status = format!("Scanning {}/{}", index + 1, total);
+                }
+                Ok(ScanEvent::Finished(summary)) => {
+                    self.status = summary.to_string();
+                    self.finished = true;
+                }
+                Ok(ScanEvent::Failed(error)) => {
+                    self.status = String::from("Scan failed");
+                    self.finished = true;
+                    self.error = Some(error);
+                }
+                Err(TryRecvError::Empty) => return changed,
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    return true;
+                }
+            }
+
+            changed = true;
+        }
+    }
+
+    pub fn get_status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+impl std::fmt::Display for ScanSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Scanned {} files", self.scanned)?;
+
+        if self.written > 0 {
+            write!(f, ", tagged {}", self.written)?;
+        }
+
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+
+        Ok(())
+    }
+}
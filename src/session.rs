@@ -0,0 +1,108 @@
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf, time::Duration};
+
+use crate::{paths, song::Song};
+
+/// The queue and where the current song was, saved when the program quits
+/// and restored on the next start.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Session {
+    /// Position in the current song in seconds.
+    position: f64,
+    songs: Vec<SessionSong>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SessionSong {
+    path: PathBuf,
+    title: Option<String>,
+    artist: Option<String>,
+    /// Segment of a CUE sheet track in seconds.
+    start: Option<f64>,
+    end: Option<f64>,
+}
+
+impl Session {
+    pub fn new(songs: &[Song], position: Duration) -> Session {
+        Session {
+            position: position.as_secs_f64(),
+            songs: songs
+                .iter()
+                .map(|song| SessionSong {
+                    path: song.get_path(),
+                    title: song.get_title().ok().flatten(),
+                    artist: song.get_artist().ok().flatten(),
+                    start: song.get_start().map(|start| start.as_secs_f64()),
+                    end: song.get_end().map(|end| end.as_secs_f64()),
+                })
+                .collect(),
+        }
+    }
+
+    /// The last session, an empty one if there is none.
+    pub fn load() -> color_eyre::Result<Session> {
+        let path = session_path()?;
+
+        if !path.exists() {
+            return Ok(Session::default());
+        }
+
+        toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| eyre!("Invalid session file {}. {e}", path.display()))
+    }
+
+    pub fn save(&self) -> color_eyre::Result<()> {
+        let content =
+            toml::to_string_pretty(self).map_err(|e| eyre!("Failed to write session. {e}"))?;
+
+        fs::write(session_path()?, content)?;
+
+        Ok(())
+    }
+
+    /// Songs whose files are still there.
+    pub fn get_songs(&self) -> Vec<Song> {
+        self.songs
+            .iter()
+            .filter(|song| song.path.is_file())
+            .map(|entry| {
+                let mut song = Song::new(entry.path.clone());
+
+                if let Some(title) = &entry.title {
+                    song.set_title(title.clone());
+                }
+
+                if let Some(artist) = &entry.artist {
+                    song.set_artist(artist.clone());
+                }
+
+                if let Some(start) = entry.start.and_then(seconds) {
+                    song.set_segment(start, entry.end.and_then(seconds));
+                }
+
+                song
+            })
+            .collect()
+    }
+
+    /// Position in the current song, zero if its file is gone.
+    pub fn get_position(&self) -> Duration {
+        match self.songs.first() {
+            Some(song) if song.path.is_file() => seconds(self.position).unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// None for values a hand-edited file may have, like negative ones.
+fn seconds(seconds: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+fn session_path() -> color_eyre::Result<PathBuf> {
+    let mut path = paths::data_dir()?;
+    path.push("session.toml");
+    Ok(path)
+}
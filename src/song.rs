@@ -1,29 +1,36 @@
-use audiotags::Tag;
-use std::path::PathBuf;
+use rodio::Source;
+use std::{path::PathBuf, time::Duration};
+
+use crate::{format, gapless::Track};
 
 #[derive(Clone)]
-#[allow(dead_code)]
 pub struct Song {
     title: Option<String>,
     artist: Option<String>,
     path: PathBuf,
+    start: Option<Duration>,
+    end: Option<Duration>,
 }
 
 impl Song {
     pub fn new(path: PathBuf) -> Song {
         let mut title: Option<String> = None;
-        let artist: Option<String> = Tag::new()
-            .read_from_path(path.clone())
-            .ok()
-            .and_then(|tag| tag.artist().map(|a| a.to_owned()));
+        let artist: Option<String> = format::read_tags(&path).ok().and_then(|tags| tags.artist);
 
         if let Some(str) = path.to_str()
             && let Some(filename) = str.split("/").last()
-                && let Some(split) = filename.rsplit_once(".") {
-                title = Some(split.0.to_string());
+            && let Some(split) = filename.rsplit_once(".")
+        {
+            title = Some(split.0.to_string());
+        }
+
+        Song {
+            title,
+            artist,
+            path,
+            start: None,
+            end: None,
         }
-        
-        Song { title, artist, path }
     }
 
     pub fn get_title(&self) -> color_eyre::Result<Option<String>> {
@@ -33,4 +40,54 @@ impl Song {
     pub fn get_artist(&self) -> color_eyre::Result<Option<String>> {
         Ok(self.artist.clone())
     }
+
+    pub fn get_path(&self) -> PathBuf {
+        self.path.clone()
+    }
+
+    pub fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    pub fn set_artist(&mut self, artist: String) {
+        self.artist = Some(artist);
+    }
+
+    /// Restricts playback to a part of the file, used for tracks of
+    /// single-file album rips described by a CUE sheet.
+    pub fn set_segment(&mut self, start: Duration, end: Option<Duration>) {
+        self.start = Some(start);
+        self.end = end;
+    }
+
+    pub fn get_start(&self) -> Option<Duration> {
+        self.start
+    }
+
+    pub fn get_end(&self) -> Option<Duration> {
+        self.end
+    }
+
+    /// Opens the song `offset` into the track, only playing its segment if
+    /// it has one.
+    pub fn open(&self, offset: Duration) -> color_eyre::Result<Track> {
+        let mut source = format::open(&self.path)?;
+
+        let start = self.start.unwrap_or_default() + offset;
+        let mut skip = Duration::ZERO;
+
+        // Not every decoder can seek, decode up to the start instead.
+        if !start.is_zero() && source.try_seek(start).is_err() {
+            skip = start;
+        }
+
+        Ok(match self.end {
+            Some(end) => Box::new(
+                source
+                    .skip_duration(skip)
+                    .take_duration(end.saturating_sub(start)),
+            ),
+            None => Box::new(source.skip_duration(skip)),
+        })
+    }
 }
@@ -0,0 +1,323 @@
+use color_eyre::eyre::eyre;
+use rodio::Source;
+use std::{
+    collections::{HashMap, VecDeque},
+    f32::consts::PI,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::paths;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+pub const SPEED_STEP: f32 = 0.1;
+
+/// Length of the overlapping segments.
+const SEGMENT: Duration = Duration::from_millis(30);
+/// How far a segment may be moved to line up with the previous one.
+const TOLERANCE: Duration = Duration::from_millis(10);
+/// Only every nth frame is compared while searching, full resolution isn't
+/// needed to find the best overlap.
+const DECIMATION: usize = 4;
+
+/// Playback speed shared between the player and the audio thread.
+#[derive(Clone)]
+pub struct SpeedHandle {
+    speed: Arc<AtomicU32>,
+}
+
+impl SpeedHandle {
+    pub fn new(speed: f32) -> SpeedHandle {
+        SpeedHandle {
+            speed: Arc::new(AtomicU32::new(speed.to_bits())),
+        }
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.speed.load(Ordering::SeqCst))
+    }
+
+    pub fn set(&self, speed: f32) {
+        self.speed
+            .store(clamp_speed(speed).to_bits(), Ordering::SeqCst);
+    }
+}
+
+/// Rounds to the speed step, so repeated steps land on 1.0 again.
+pub fn clamp_speed(speed: f32) -> f32 {
+    ((speed / SPEED_STEP).round() * SPEED_STEP).clamp(MIN_SPEED, MAX_SPEED)
+}
+
+/// Changes the tempo of `inner` without changing its pitch (WSOLA). The
+/// input is cut into overlapping segments which are laid out at a different
+/// distance than they were read, each segment is shifted slightly to match
+/// the waveform of the previous one so the overlap doesn't cancel out.
+pub struct TimeStretch<S> {
+    inner: S,
+    speed: SpeedHandle,
+    channels: usize,
+    window: Vec<f32>,
+    /// Frames between two output segments, half a segment.
+    hop: usize,
+    tolerance: usize,
+    /// Interleaved input, `input_start` is the frame index of its first
+    /// sample.
+    input: Vec<f32>,
+    input_start: u64,
+    /// Frame index where the source ended.
+    end: Option<u64>,
+    /// Where the next segment would start without alignment.
+    nominal: f64,
+    /// Start of the last segment.
+    previous: Option<u64>,
+    /// Second half of the last segment, added to the next one.
+    overlap: Vec<f32>,
+    output: VecDeque<f32>,
+}
+
+impl<S: Source> TimeStretch<S> {
+    pub fn new(inner: S, speed: SpeedHandle) -> TimeStretch<S> {
+        let channels = inner.channels().max(1) as usize;
+        let rate = inner.sample_rate() as f32;
+
+        let hop = (SEGMENT.as_secs_f32() * rate / 2.0) as usize;
+        let segment = hop * 2;
+
+        // A periodic Hann window, two of them overlapping by half sum to one.
+        let window = (0..segment)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / segment as f32).cos())
+            .collect();
+
+        TimeStretch {
+            inner,
+            speed,
+            channels,
+            window,
+            hop,
+            tolerance: (TOLERANCE.as_secs_f32() * rate) as usize,
+            input: vec![],
+            input_start: 0,
+            end: None,
+            nominal: 0.0,
+            previous: None,
+            overlap: vec![0.0; hop * channels],
+            output: VecDeque::new(),
+        }
+    }
+
+    fn input_end(&self) -> u64 {
+        self.input_start + (self.input.len() / self.channels) as u64
+    }
+
+    /// Reads input until the frame before `end` is buffered, padding with
+    /// silence once the source ended.
+    fn fill(&mut self, end: u64) {
+        while self.input_end() < end {
+            let frame = self.input_end();
+
+            for _ in 0..self.channels {
+                let sample = match self.inner.next() {
+                    Some(sample) => sample,
+                    None => {
+                        self.end.get_or_insert(frame);
+                        0.0
+                    }
+                };
+
+                self.input.push(sample);
+            }
+        }
+    }
+
+    /// Sum of all channels of an input frame.
+    fn mono(&self, frame: u64) -> f32 {
+        let start = (frame - self.input_start) as usize * self.channels;
+        self.input[start..start + self.channels].iter().sum()
+    }
+
+    fn process_segment(&mut self) {
+        let speed = self.speed.get() as f64;
+        let segment = self.window.len();
+
+        let start = match self.previous {
+            // Played at normal speed, the natural continuation is exact.
+            Some(previous) if speed == 1.0 => previous + self.hop as u64,
+            Some(previous) => {
+                let natural = previous + self.hop as u64;
+                let nominal = self.nominal.round() as u64;
+                let low = nominal
+                    .saturating_sub(self.tolerance as u64)
+                    .max(self.input_start);
+                let high = nominal + self.tolerance as u64;
+
+                self.fill(high.max(natural) + segment as u64);
+
+                let mut best = nominal.max(low);
+                let mut best_score = f32::MIN;
+
+                for candidate in low..=high {
+                    let score: f32 = (0..self.hop)
+                        .step_by(DECIMATION)
+                        .map(|i| self.mono(natural + i as u64) * self.mono(candidate + i as u64))
+                        .sum();
+
+                    if score > best_score {
+                        best_score = score;
+                        best = candidate;
+                    }
+                }
+
+                best
+            }
+            None => self.nominal.round() as u64,
+        };
+
+        self.fill(start + segment as u64);
+
+        let offset = (start - self.input_start) as usize * self.channels;
+
+        // A segment that continues the last one without a shift overlaps the
+        // same input, whose two windows sum to one. Passing the input through
+        // keeps normal speed bit-exact.
+        let continuous = self
+            .previous
+            .is_none_or(|previous| start == previous + self.hop as u64);
+
+        for i in 0..segment {
+            for channel in 0..self.channels {
+                let input = self.input[offset + i * self.channels + channel];
+                let sample = input * self.window[i];
+
+                if i < self.hop {
+                    self.output.push_back(match continuous {
+                        true => input,
+                        false => self.overlap[i * self.channels + channel] + sample,
+                    });
+                } else {
+                    self.overlap[(i - self.hop) * self.channels + channel] = sample;
+                }
+            }
+        }
+
+        self.previous = Some(start);
+
+        self.nominal = if speed == 1.0 {
+            (start + self.hop as u64) as f64
+        } else {
+            self.nominal + self.hop as f64 * speed
+        };
+
+        // Everything before the earliest frame the next segment may read.
+        let keep = (self.nominal as u64)
+            .saturating_sub(self.tolerance as u64)
+            .min(start + self.hop as u64);
+
+        if keep > self.input_start {
+            let drop = ((keep - self.input_start) as usize * self.channels).min(self.input.len());
+            self.input.drain(..drop);
+            self.input_start += (drop / self.channels) as u64;
+        }
+    }
+}
+
+impl<S: Source> Iterator for TimeStretch<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.output.is_empty() {
+            if self
+                .end
+                .is_some_and(|end| self.previous.unwrap_or(0) + self.hop as u64 >= end)
+            {
+                return None;
+            }
+
+            self.process_segment();
+        }
+
+        self.output.pop_front()
+    }
+}
+
+impl<S: Source> Source for TimeStretch<S> {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Speeds chosen for single files, kept for spoken-word content so an
+/// audiobook always continues at the speed it was last listened to.
+#[derive(Clone)]
+pub struct SpeedMemory {
+    speeds: Arc<Mutex<HashMap<PathBuf, f32>>>,
+}
+
+impl SpeedMemory {
+    pub fn load() -> color_eyre::Result<SpeedMemory> {
+        let path = memory_path()?;
+
+        let speeds = if path.exists() {
+            fs::read_to_string(path)?
+                .lines()
+                .filter_map(|line| {
+                    let (speed, path) = line.split_once('\t')?;
+                    Some((paths::unescape(path), speed.parse().ok()?))
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        Ok(SpeedMemory {
+            speeds: Arc::new(Mutex::new(speeds)),
+        })
+    }
+
+    pub fn get(&self, path: &Path) -> Option<f32> {
+        self.speeds.lock().ok()?.get(path).copied()
+    }
+
+    pub fn set(&self, path: &Path, speed: f32) -> color_eyre::Result<()> {
+        let mut speeds = self
+            .speeds
+            .lock()
+            .map_err(|_| eyre!("Speed memory is poisoned."))?;
+
+        speeds.insert(path.to_path_buf(), speed);
+
+        let mut writer = BufWriter::new(File::create(memory_path()?)?);
+
+        for (path, speed) in speeds.iter() {
+            writeln!(writer, "{speed}\t{}", paths::escape(path))?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+fn memory_path() -> color_eyre::Result<PathBuf> {
+    let mut path = paths::data_dir()?;
+    path.push("speeds.tsv");
+    Ok(path)
+}
@@ -0,0 +1,84 @@
+use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+    terminal::{enable_raw_mode, EnterAlternateScreen},
+};
+use ratatui::DefaultTerminal;
+use signal_hook::{
+    consts::{SIGCONT, SIGHUP, SIGINT, SIGSTOP, SIGTERM, SIGTSTP},
+    low_level,
+};
+use std::{io::stdout, mem, panic, sync::Mutex, thread};
+
+/// Signals the app handles itself, see [`App::run`](crate::app::App::run).
+pub const SIGNALS: [i32; 5] = [SIGINT, SIGTERM, SIGHUP, SIGTSTP, SIGCONT];
+
+/// Panic messages of background threads the app hasn't shown yet.
+static PANICS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+/// Switches to raw mode, the alternate screen and mouse capture, and makes
+/// sure a panic gives the terminal back before it is reported.
+pub fn init() -> color_eyre::Result<DefaultTerminal> {
+    let terminal = ratatui::try_init()?;
+    execute!(stdout(), EnableMouseCapture)?;
+
+    set_panic_hook();
+
+    Ok(terminal)
+}
+
+/// Undoes [`init`], errors are ignored as there is nothing left to draw
+/// them on.
+pub fn restore() {
+    let _ = execute!(stdout(), DisableMouseCapture);
+    ratatui::restore();
+}
+
+/// Hands the terminal back to the shell and stops the process, like Ctrl-Z
+/// does for other programs. Returns once the shell continues it.
+pub fn suspend(terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
+    restore();
+    low_level::raise(SIGSTOP)?;
+    resume(terminal)
+}
+
+/// Takes the terminal over again after the process was stopped, the shell
+/// may have changed its mode and screen in the meantime.
+pub fn resume(terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+    terminal.clear()?;
+
+    Ok(())
+}
+
+/// Panics of background threads since the last call, see
+/// [`set_panic_hook`].
+pub fn take_panics() -> Vec<String> {
+    PANICS
+        .lock()
+        .map(|mut panics| mem::take(&mut *panics))
+        .unwrap_or_default()
+}
+
+/// Panics of the UI thread restore the terminal before they are printed.
+/// Other threads don't print over the UI, their message is kept for the
+/// app to show. The player thread's panic is shown once it is restarted.
+fn set_panic_hook() {
+    let hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| match thread::current().name() {
+        Some("main") => {
+            restore();
+            hook(info);
+        }
+        Some("player") => {}
+        _ => {
+            let message = info.payload_as_str().unwrap_or("no message");
+
+            if let Ok(mut panics) = PANICS.lock() {
+                panics.push(format!("A background task crashed. {message}"));
+            }
+        }
+    }));
+}
@@ -0,0 +1,227 @@
+use color_eyre::eyre::eyre;
+use ratatui::style::{palette::tailwind, Color, Modifier, Style, Stylize};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, str::FromStr};
+
+use crate::paths;
+
+/// Themes that are always available.
+pub const BUILT_IN: [&str; 3] = ["default", "high-contrast", "16-color"];
+
+/// Styles of the parts of the UI.
+#[derive(Clone)]
+pub struct Theme {
+    name: String,
+    /// The line with the window tabs.
+    pub header: Style,
+    /// The tab of the current window.
+    pub active_tab: Style,
+    pub footer: Style,
+    /// The selected entry of a list.
+    pub selection: Style,
+    /// The song playing right now, in the queue and in the browser.
+    pub playing_track: Style,
+    pub directory: Style,
+    pub file: Style,
+    pub error: Style,
+    /// The visualizer and the played part of the overview.
+    pub accent: Style,
+    /// Secondary text, like completion candidates.
+    pub muted: Style,
+}
+
+/// A style in a theme file, anything left out is taken from the base.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StyleFile {
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: Option<bool>,
+    italic: Option<bool>,
+    underlined: Option<bool>,
+    reversed: Option<bool>,
+}
+
+/// A user theme, e.g. `themes/nord.toml` in the config directory:
+///
+/// ```toml
+/// base = "16-color"
+///
+/// [selection]
+/// fg = "black"
+/// bg = "#88c0d0"
+/// bold = true
+/// ```
+#[derive(Deserialize)]
+struct ThemeFile {
+    /// Built-in theme the file changes, "default" if unset.
+    base: Option<String>,
+    #[serde(flatten)]
+    styles: HashMap<String, StyleFile>,
+}
+
+impl Theme {
+    /// Loads a built-in theme or one from the `themes` directory.
+    pub fn load(name: &str) -> color_eyre::Result<Theme> {
+        if let Some(theme) = built_in(name) {
+            return Ok(theme);
+        }
+
+        let mut path = paths::themes_dir()?;
+        path.push(format!("{name}.toml"));
+
+        if !path.exists() {
+            return Err(eyre!("Theme \"{name}\" not found."));
+        }
+
+        let file: ThemeFile = toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| eyre!("Invalid theme file {}. {e}", path.display()))?;
+
+        let base = file.base.as_deref().unwrap_or("default");
+        let mut theme = built_in(base)
+            .ok_or_else(|| eyre!("Unknown base theme \"{base}\" in {}.", path.display()))?;
+
+        theme.name = name.to_string();
+
+        for (style_name, style) in file.styles {
+            let target = theme
+                .style_mut(&style_name)
+                .ok_or_else(|| eyre!("Unknown style \"{style_name}\" in {}.", path.display()))?;
+
+            *target = style
+                .apply(*target)
+                .map_err(|e| eyre!("{e} in {}.", path.display()))?;
+        }
+
+        Ok(theme)
+    }
+
+    /// Names of the built-in themes and of the user themes.
+    pub fn list() -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN.iter().map(|name| name.to_string()).collect();
+
+        if let Ok(entries) = paths::themes_dir().and_then(|dir| Ok(fs::read_dir(dir)?)) {
+            let mut user: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extension == "toml")
+                })
+                .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+                .filter(|name| !names.contains(name))
+                .collect();
+
+            user.sort();
+            names.extend(user);
+        }
+
+        names
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The style as named in theme files.
+    fn style_mut(&mut self, name: &str) -> Option<&mut Style> {
+        Some(match name {
+            "header" => &mut self.header,
+            "active-tab" => &mut self.active_tab,
+            "footer" => &mut self.footer,
+            "selection" => &mut self.selection,
+            "playing-track" => &mut self.playing_track,
+            "directory" => &mut self.directory,
+            "file" => &mut self.file,
+            "error" => &mut self.error,
+            "accent" => &mut self.accent,
+            "muted" => &mut self.muted,
+            _ => return None,
+        })
+    }
+}
+
+impl StyleFile {
+    fn apply(&self, mut style: Style) -> color_eyre::Result<Style> {
+        if let Some(fg) = &self.fg {
+            style = style.fg(parse_color(fg)?);
+        }
+
+        if let Some(bg) = &self.bg {
+            style = style.bg(parse_color(bg)?);
+        }
+
+        for (enabled, modifier) in [
+            (self.bold, Modifier::BOLD),
+            (self.italic, Modifier::ITALIC),
+            (self.underlined, Modifier::UNDERLINED),
+            (self.reversed, Modifier::REVERSED),
+        ] {
+            style = match enabled {
+                Some(true) => style.add_modifier(modifier),
+                Some(false) => style.remove_modifier(modifier),
+                None => style,
+            };
+        }
+
+        Ok(style)
+    }
+}
+
+/// A color name like "red" or "light-blue", "#rrggbb", or an index into
+/// the terminal's 256 colors.
+fn parse_color(color: &str) -> color_eyre::Result<Color> {
+    Color::from_str(color).map_err(|_| eyre!("Invalid color \"{color}\""))
+}
+
+fn built_in(name: &str) -> Option<Theme> {
+    let theme = match name {
+        "default" => Theme {
+            name: String::new(),
+            header: Style::new(),
+            active_tab: Style::new().bold().bg(tailwind::BLUE.c700),
+            footer: Style::new(),
+            selection: Style::new().bg(tailwind::BLUE.c700),
+            playing_track: Style::new().bold().fg(tailwind::BLUE.c400),
+            directory: Style::new().fg(tailwind::SKY.c300),
+            file: Style::new(),
+            error: Style::new().fg(tailwind::RED.c400),
+            accent: Style::new().fg(tailwind::BLUE.c400),
+            muted: Style::new().fg(tailwind::SLATE.c500),
+        },
+        "high-contrast" => Theme {
+            name: String::new(),
+            header: Style::new().fg(Color::White).bg(Color::Black),
+            active_tab: Style::new().bold().fg(Color::Black).bg(Color::Yellow),
+            footer: Style::new().bold().fg(Color::White).bg(Color::Black),
+            selection: Style::new().bold().fg(Color::Black).bg(Color::White),
+            playing_track: Style::new().bold().fg(Color::Yellow),
+            directory: Style::new().bold().fg(Color::Cyan),
+            file: Style::new().fg(Color::White),
+            error: Style::new().bold().fg(Color::White).bg(Color::Red),
+            accent: Style::new().fg(Color::Yellow),
+            muted: Style::new().fg(Color::White),
+        },
+        // Only the 16 colors every terminal has, so the terminal's own
+        // palette decides the actual colors.
+        "16-color" => Theme {
+            name: String::new(),
+            header: Style::new(),
+            active_tab: Style::new().bold().fg(Color::White).bg(Color::Blue),
+            footer: Style::new(),
+            selection: Style::new().reversed(),
+            playing_track: Style::new().bold().fg(Color::Green),
+            directory: Style::new().bold().fg(Color::Blue),
+            file: Style::new(),
+            error: Style::new().fg(Color::Red),
+            accent: Style::new().fg(Color::Cyan),
+            muted: Style::new().fg(Color::DarkGray),
+        },
+        _ => return None,
+    };
+
+    Some(Theme {
+        name: name.to_string(),
+        ..theme
+    })
+}
@@ -1,10 +1,25 @@
-use crate::app::App;
+use crate::{
+    app::App,
+    eq::{GRAPHIC_FREQUENCIES, MAX_GAIN},
+    keymap::{self, Context},
+    notifications::{Level, Notification},
+    now_playing::UP_NEXT,
+    visualizer::{VisualizerMode, BARS},
+};
+use std::{
+    cell::{Cell, RefCell},
+    time::Duration,
+};
+
 use ratatui::{
-    layout::{Direction, Flex, Layout, Rect},
+    layout::{Direction, Flex, Layout, Position, Rect},
     prelude::{Constraint, Stylize},
-    style::{palette::tailwind, Style},
-    text::Line,
-    widgets::{Block, Borders, List, ListState, Paragraph},
+    symbols::Marker,
+    text::{Line, Span},
+    widgets::{
+        Axis, Bar, BarChart, BarGroup, Block, Borders, Chart, Clear, Dataset, GraphType, List,
+        ListItem, ListState, Paragraph, Wrap,
+    },
     Frame,
 };
 
@@ -13,19 +28,148 @@ pub enum Window {
     Home,
     Browser,
     Queue,
+    Playlists,
+    Equalizer,
+    Devices,
+    Visualizer,
+    NowPlaying,
+    Log,
+}
+
+impl Window {
+    /// Every window in the order of their number keys.
+    pub const ALL: [Window; 9] = [
+        Window::Home,
+        Window::Browser,
+        Window::Queue,
+        Window::Playlists,
+        Window::Equalizer,
+        Window::Devices,
+        Window::Visualizer,
+        Window::NowPlaying,
+        Window::Log,
+    ];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Window::Home => "Home",
+            Window::Browser => "Browser",
+            Window::Queue => "Queue",
+            Window::Playlists => "Playlists",
+            Window::Equalizer => "Equalizer",
+            Window::Devices => "Devices",
+            Window::Visualizer => "Visualizer",
+            Window::NowPlaying => "Now Playing",
+            Window::Log => "Log",
+        }
+    }
+
+    /// Name of the global action that opens the window.
+    fn get_action(&self) -> &'static str {
+        match self {
+            Window::Home => "home",
+            Window::Browser => "browser",
+            Window::Queue => "queue",
+            Window::Playlists => "playlists",
+            Window::Equalizer => "equalizer",
+            Window::Devices => "devices",
+            Window::Visualizer => "visualizer",
+            Window::NowPlaying => "now_playing",
+            Window::Log => "log",
+        }
+    }
+
+    /// Label of the key that opens the window.
+    fn get_key(&self, app: &App) -> String {
+        app.keymap
+            .get_key(Context::Global, self.get_action())
+            .map(keymap::key_name)
+            .unwrap_or_default()
+    }
 }
 
 pub struct Ui {
     current_window: Window,
+    /// Header tabs as drawn last, for mouse clicks.
+    tabs: RefCell<Vec<(Rect, Window)>>,
+    /// Rows of the current window's list and the index of the first
+    /// visible entry, empty if the window has no list.
+    list_area: Cell<Rect>,
+    list_offset: Cell<usize>,
+    /// Where the waveform overview was drawn last, for mouse clicks.
+    overview_area: Cell<Rect>,
+    help: bool,
+    /// First line of the help shown.
+    help_scroll: Cell<u16>,
 }
 
 impl Ui {
     pub fn new() -> Ui {
         Ui {
             current_window: Window::Home,
+            tabs: RefCell::new(vec![]),
+            list_area: Cell::new(Rect::default()),
+            list_offset: Cell::new(0),
+            overview_area: Cell::new(Rect::default()),
+            help: false,
+            help_scroll: Cell::new(0),
         }
     }
 
+    pub fn is_help_shown(&self) -> bool {
+        self.help
+    }
+
+    pub fn toggle_help(&mut self) {
+        self.help = !self.help;
+        self.help_scroll.set(0);
+    }
+
+    /// Scrolls the help by `lines`, it is clamped when drawn.
+    pub fn scroll_help(&mut self, lines: i16) {
+        self.help_scroll
+            .set(self.help_scroll.get().saturating_add_signed(lines));
+    }
+
+    pub fn get_tab_at(&self, position: Position) -> Option<Window> {
+        self.tabs
+            .borrow()
+            .iter()
+            .find(|(area, _)| area.contains(position))
+            .map(|(_, window)| window.clone())
+    }
+
+    pub fn has_list(&self) -> bool {
+        !self.list_area.get().is_empty()
+    }
+
+    /// Index of the list entry drawn at `position`.
+    pub fn get_list_index_at(&self, position: Position) -> Option<usize> {
+        let area = self.list_area.get();
+
+        area.contains(position)
+            .then(|| self.list_offset.get() + (position.y - area.y) as usize)
+    }
+
+    /// Remembers where a list was drawn. Lists are drawn with a fresh
+    /// state every frame, the offset ratatui picked is only known after
+    /// rendering.
+    fn set_list_area(&self, area: Rect, list_state: &ListState, len: usize) {
+        let rows = len
+            .saturating_sub(list_state.offset())
+            .min(area.height as usize);
+
+        self.list_area.set(Rect {
+            height: rows as u16,
+            ..area
+        });
+        self.list_offset.set(list_state.offset());
+    }
+
+    pub fn get_overview_area(&self) -> Rect {
+        self.overview_area.get()
+    }
+
     pub fn get_current_window(&self) -> Window {
         self.current_window.clone()
     }
@@ -37,42 +181,207 @@ impl Ui {
     pub fn draw(&self, app: &App, frame: &mut Frame) -> color_eyre::Result<()> {
         let layout = Layout::new(
             Direction::Vertical,
-            vec![Constraint::Max(1), Constraint::Fill(1), Constraint::Max(1)],
+            vec![
+                Constraint::Max(1),
+                Constraint::Fill(1),
+                Constraint::Max(if app.overview.get_points().is_empty() {
+                    0
+                } else {
+                    1
+                }),
+                Constraint::Max(1),
+            ],
         )
         .flex(Flex::Center)
         .split(frame.area());
 
-        self.draw_header(frame, layout[0]);
-        self.draw_footer(frame, layout[2], app)?;
+        self.list_area.set(Rect::default());
+
+        self.draw_header(app, frame, layout[0]);
+        self.draw_overview(frame, layout[2], app);
+        self.draw_footer(frame, layout[3], app)?;
 
         match self.get_current_window() {
-            Window::Home => self.home(frame, layout[1]),
+            Window::Home => self.home(app, frame, layout[1]),
             Window::Browser => self.browser(app, frame, layout[1])?,
             Window::Queue => self.queue(app, frame, layout[1])?,
+            Window::Playlists => self.playlists(app, frame, layout[1])?,
+            Window::Equalizer => self.equalizer(app, frame, layout[1]),
+            Window::Devices => self.devices(app, frame, layout[1]),
+            Window::Visualizer => self.visualizer(app, frame, layout[1]),
+            Window::NowPlaying => self.now_playing(app, frame, layout[1])?,
+            Window::Log => self.log(app, frame, layout[1]),
         };
 
+        if self.help {
+            // Clicks go to the help, not to the list underneath.
+            self.list_area.set(Rect::default());
+            self.draw_help(app, frame, layout[1]);
+        }
+
+        if let Some(toast) = app.notifications.get_toast() {
+            self.draw_toast(app, frame, layout[1], toast);
+        }
+
         Ok(())
     }
 
-    fn draw_header(&self, frame: &mut Frame, area: Rect) {
-        let block = Block::new()
-            .title(
-                Line::from(format!(
-                    " {} ",
-                    match self.current_window {
-                        Window::Home => "Home",
-                        Window::Browser => "Browser",
-                        Window::Queue => "Queue",
-                    }
-                ))
-                .centered(),
-            )
-            .borders(Borders::TOP);
+    /// Every binding of the active keymap, grouped by context.
+    fn draw_help(&self, app: &App, frame: &mut Frame, area: Rect) {
+        let mut lines = vec![];
 
-        frame.render_widget(block, area);
+        for context in Context::ALL {
+            if !lines.is_empty() {
+                lines.push(Line::default());
+            }
+
+            lines.push(Line::from(context.get_title()).bold());
+
+            for binding in app.keymap.get_bindings(context) {
+                lines.push(Line::from(format!(
+                    "  {:<10} {}",
+                    keymap::key_name(binding.key),
+                    binding.description
+                )));
+            }
+        }
+
+        let [area] = Layout::horizontal([Constraint::Max(60)])
+            .flex(Flex::Center)
+            .areas(area);
+
+        let rows = area.height.saturating_sub(2);
+        let scroll = self
+            .help_scroll
+            .get()
+            .min((lines.len() as u16).saturating_sub(rows));
+        self.help_scroll.set(scroll);
+
+        let block = Block::bordered()
+            .title(Line::from(" Help ").centered())
+            .title_bottom(Line::from(" j/k to scroll, Esc to close ").centered());
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(Paragraph::new(lines).block(block).scroll((scroll, 0)), area);
+    }
+
+    /// The newest notification in the bottom right corner.
+    fn draw_toast(&self, app: &App, frame: &mut Frame, area: Rect, toast: &Notification) {
+        let (title, style) = match toast.level {
+            Level::Info => (" Info ", app.theme.accent),
+            Level::Error => (" Error ", app.theme.error),
+        };
+
+        let text = format_notification(toast);
+        let width = (text.chars().count() as u16 + 2).clamp(20, (area.width / 2).max(20));
+        let lines = (text.chars().count() as u16).div_ceil(width.saturating_sub(2).max(1));
+
+        let toast_area = Rect {
+            x: area.right().saturating_sub(width + 1),
+            y: area.bottom().saturating_sub(lines.min(4) + 2),
+            width,
+            height: lines.min(4) + 2,
+        }
+        .intersection(area);
+
+        frame.render_widget(Clear, toast_area);
+        frame.render_widget(
+            Paragraph::new(text)
+                .wrap(Wrap { trim: true })
+                .block(Block::bordered().title(title).border_style(style)),
+            toast_area,
+        );
+    }
+
+    /// Tabs of every window, centered on the top border.
+    fn draw_header(&self, app: &App, frame: &mut Frame, area: Rect) {
+        frame.render_widget(
+            Block::new().borders(Borders::TOP).style(app.theme.header),
+            area,
+        );
+
+        let labels: Vec<String> = Window::ALL
+            .iter()
+            .map(|window| format!(" {} {} ", window.get_key(app), window.get_name()))
+            .collect();
+
+        let width: u16 = labels
+            .iter()
+            .map(|label| label.chars().count() as u16)
+            .sum();
+        let mut x = area.x + area.width.saturating_sub(width) / 2;
+
+        let mut tabs = self.tabs.borrow_mut();
+        tabs.clear();
+
+        for (window, label) in Window::ALL.iter().zip(labels) {
+            let tab = Rect::new(x, area.y, label.chars().count() as u16, 1).intersection(area);
+
+            let line = if *window == self.current_window {
+                Line::from(label).style(app.theme.active_tab)
+            } else {
+                Line::from(label)
+            };
+
+            frame.render_widget(line, tab);
+            tabs.push((tab, window.clone()));
+
+            x = tab.right();
+        }
+    }
+
+    /// One line of the current track's waveform, the part already played
+    /// highlighted.
+    fn draw_overview(&self, frame: &mut Frame, area: Rect, app: &App) {
+        const LEVELS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+        self.overview_area.set(area);
+
+        let points = app.overview.get_points();
+
+        if points.is_empty() || area.width == 0 {
+            return;
+        }
+
+        let played = match app.player_controller.get_length() {
+            Some(length) if !length.is_zero() => {
+                (app.player_controller.get_position().as_secs_f32() / length.as_secs_f32()
+                    * area.width as f32) as usize
+            }
+            _ => 0,
+        };
+
+        let width = area.width as usize;
+        let column = |i: usize| -> String {
+            let first = i * points.len() / width;
+            let last = ((i + 1) * points.len() / width).max(first + 1);
+            let peak = points[first..last]
+                .iter()
+                .fold(0.0, |peak: f32, point| peak.max(point.peak));
+
+            LEVELS[(peak * 8.0).round().clamp(1.0, 8.0) as usize].to_string()
+        };
+
+        let line = Line::from(vec![
+            Span::styled(
+                (0..played.min(width)).map(column).collect::<String>(),
+                app.theme.accent,
+            ),
+            Span::styled(
+                (played.min(width)..width).map(column).collect::<String>(),
+                app.theme.muted,
+            ),
+        ]);
+
+        frame.render_widget(line, area);
     }
 
     fn draw_footer(&self, frame: &mut Frame, area: Rect, app: &App) -> color_eyre::Result<()> {
+        if let Some(input) = app.command_line.get_input() {
+            self.draw_command_line(app, frame, area, input);
+            return Ok(());
+        }
+
         let block = Block::new()
             .title(
                 Line::from(
@@ -108,14 +417,78 @@ impl Ui {
                 )
                 .centered(),
             )
-            .borders(Borders::TOP);
+            .borders(Borders::TOP)
+            .style(app.theme.footer);
+
+        let mut info = vec![];
+
+        if app.player_controller.get_device().is_none() {
+            info.push(String::from("No output"));
+        }
+
+        if app.player_controller.get_volume() < 100 {
+            info.push(format!("Vol {}%", app.player_controller.get_volume()));
+        }
+
+        if app.player_controller.is_shuffled() {
+            info.push(String::from("Shuffle"));
+        }
+
+        if app.player_controller.get_speed() != 1.0 {
+            info.push(format!("{:.1}x", app.player_controller.get_speed()));
+        }
+
+        if let Some(gain) = app.player_controller.get_replay_gain() {
+            info.push(format!("RG {gain:+.1} dB"));
+        }
+
+        let block = if info.is_empty() {
+            block
+        } else {
+            block.title(Line::from(format!(" {} ", info.join(" "))).right_aligned())
+        };
+
+        let status = match (app.command_line.get_status(), &app.scan) {
+            (Some(status), _) if app.command_line.is_error() => {
+                Some(Line::from(format!(" {status} ")).style(app.theme.error))
+            }
+            (Some(status), _) => Some(Line::from(format!(" {status} "))),
+            (None, Some(scan)) => Some(Line::from(format!(" {} ", scan.get_status()))),
+            (None, None) => None,
+        };
+
+        let block = match status {
+            Some(status) => block.title(status.left_aligned()),
+            None => block,
+        };
 
         frame.render_widget(block, area);
 
         Ok(())
     }
 
-    fn home(&self, frame: &mut Frame, area: Rect) {
+    /// The line being typed, with completion candidates to its right.
+    fn draw_command_line(&self, app: &App, frame: &mut Frame, area: Rect, input: &str) {
+        let line = format!(":{input}");
+        let width = (line.chars().count() as u16).min(area.width);
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(Line::from(line), area);
+        frame.set_cursor_position(Position::new(area.x + width, area.y));
+
+        if let Some(status) = app.command_line.get_status() {
+            let rest = Rect {
+                x: area.x + width + 2,
+                width: area.width.saturating_sub(width + 2),
+                ..area
+            }
+            .intersection(area);
+
+            frame.render_widget(Line::from(status).style(app.theme.muted), rest);
+        }
+    }
+
+    fn home(&self, app: &App, frame: &mut Frame, area: Rect) {
         let sub_layout = Layout::new(
             Direction::Vertical,
             vec![Constraint::Max(1), Constraint::Percentage(50)],
@@ -127,7 +500,16 @@ impl Ui {
 
         frame.render_widget(main_text, sub_layout[0]);
 
-        let paragraph = Paragraph::new("1: Home 2: Browser 3: Queue").centered();
+        let mut keys: Vec<String> = Window::ALL
+            .iter()
+            .map(|window| format!("{}: {}", window.get_key(app), window.get_name()))
+            .collect();
+
+        if let Some(key) = app.keymap.get_key(Context::Global, "help") {
+            keys.push(format!("{}: Help", keymap::key_name(key)));
+        }
+
+        let paragraph = Paragraph::new(keys.join(" ")).centered();
 
         frame.render_widget(paragraph, sub_layout[1]);
     }
@@ -135,36 +517,54 @@ impl Ui {
     fn browser(&self, app: &App, frame: &mut Frame, area: Rect) -> color_eyre::Result<()> {
         let mut list_state = ListState::default();
 
-        const SELECTED_STYLE: Style = Style::new().bg(tailwind::BLUE.c700);
+        let playing = app
+            .player_controller
+            .queue
+            .get_current_song()?
+            .map(|song| song.get_path());
 
-        let list_items: Vec<String> = app
+        let list_items: Vec<ListItem> = app
             .browser
             .list_dir()?
             .iter()
             .filter_map(|entry| {
-                let parts: Vec<&str> = entry.split("/").collect();
-                if parts.len() >= 2 {
-                    Some(format!(
-                        "{}/{}",
-                        parts[parts.len() - 2],
-                        parts[parts.len() - 1]
-                    ))
-                } else if parts.len() == 1 {
-                    Some(parts[0].to_string())
+                // Names that aren't UTF-8 are shown lossily, the entry
+                // keeps the real path.
+                let parts: Vec<_> = entry
+                    .iter()
+                    .map(|part| part.to_string_lossy())
+                    .filter(|part| part != "/")
+                    .collect();
+                let name = match parts.as_slice() {
+                    [.., parent, name] => format!("{parent}/{name}"),
+                    [name] => name.to_string(),
+                    [] => return None,
+                };
+
+                let path = &app.browser.get_current_path().join(entry);
+                let style = if playing.as_deref() == Some(path.as_path()) {
+                    app.theme.playing_track
+                } else if path.is_dir() {
+                    app.theme.directory
                 } else {
-                    None
-                }
+                    app.theme.file
+                };
+
+                Some(ListItem::new(name).style(style))
             })
             .collect();
 
         let list = List::new(list_items)
-            .highlight_style(SELECTED_STYLE)
+            .highlight_style(app.theme.selection)
             .highlight_symbol("> ")
             .highlight_spacing(ratatui::widgets::HighlightSpacing::Always);
 
         list_state.select(Some(app.browser.get_selected()? as usize));
 
+        let len = list.len();
+
         frame.render_stateful_widget(list, area, &mut list_state);
+        self.set_list_area(area, &list_state, len);
 
         Ok(())
     }
@@ -172,23 +572,533 @@ impl Ui {
     fn queue(&self, app: &App, frame: &mut Frame, area: Rect) -> color_eyre::Result<()> {
         let queue = app.player_controller.queue.get_queue()?;
 
-        let mut list_items: Vec<String> = Vec::new();
+        let mut list_items: Vec<ListItem> = Vec::new();
 
         if queue.is_empty() {
-            list_items.push(String::from("Empty"))
+            list_items.push(ListItem::new("Empty"))
         } else {
             list_items = queue
                 .iter()
                 .filter_map(|song| song.get_title().ok()?)
                 .enumerate()
-                .map(|(i, song)| format!("{}. {}", i + 1, song))
+                .map(|(i, song)| {
+                    let item = ListItem::new(format!("{}. {}", i + 1, song));
+
+                    // The first entry is the one playing.
+                    match i {
+                        0 => item.style(app.theme.playing_track),
+                        _ => item,
+                    }
+                })
                 .collect();
         }
 
-        let list = List::new(list_items);
+        let list = List::new(list_items)
+            .highlight_style(app.theme.selection)
+            .highlight_symbol("> ")
+            .highlight_spacing(ratatui::widgets::HighlightSpacing::Always);
+
+        let mut list_state = ListState::default();
+
+        if !queue.is_empty() {
+            list_state.select(Some(app.player_controller.queue.get_selected()));
+        }
+
+        let len = list.len();
 
-        frame.render_widget(list, area);
+        frame.render_stateful_widget(list, area, &mut list_state);
+        self.set_list_area(area, &list_state, len);
 
         Ok(())
     }
+
+    fn playlists(&self, app: &App, frame: &mut Frame, area: Rect) -> color_eyre::Result<()> {
+        let playlists = &app.playlists;
+
+        let bottom_line = if let Some(input) = playlists.get_input() {
+            Some(format!("{}: {}_", input.get_prompt(), input.get_text()))
+        } else {
+            playlists.get_status().map(|status| status.to_string())
+        };
+
+        let layout = Layout::new(
+            Direction::Vertical,
+            vec![
+                Constraint::Fill(1),
+                Constraint::Max(if bottom_line.is_some() { 1 } else { 0 }),
+            ],
+        )
+        .split(area);
+
+        let mut list_state = ListState::default();
+
+        let (title, list_items) = if let Some(detail) = playlists.get_detail() {
+            list_state.select(Some(detail.get_selected() as usize));
+
+            let items: Vec<String> = detail
+                .get_entries()
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    let title = entry.get_title().unwrap_or_else(|| {
+                        entry
+                            .get_path()
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_default()
+                    });
+
+                    match entry.get_artist() {
+                        Some(artist) => format!("{}. {} - {}", i + 1, artist, title),
+                        None => format!("{}. {}", i + 1, title),
+                    }
+                })
+                .collect();
+
+            let title = match detail.get_rule() {
+                Some(rule) => format!(" {} [{}] ", detail.get_name(), rule),
+                None => format!(" {} ", detail.get_name()),
+            };
+
+            (title, items)
+        } else {
+            list_state.select(Some(playlists.get_selected()? as usize));
+
+            let items = playlists
+                .list()?
+                .iter()
+                .map(|info| {
+                    if info.is_smart() {
+                        format!("{} (smart)", info.get_name())
+                    } else {
+                        info.get_name().to_string()
+                    }
+                })
+                .collect();
+
+            (String::from(" Saved playlists "), items)
+        };
+
+        let list_items = if list_items.is_empty() {
+            vec![String::from("Empty")]
+        } else {
+            list_items
+        };
+
+        let block = Block::new().title(title);
+        let inner = block.inner(layout[0]);
+
+        let list = List::new(list_items)
+            .block(block)
+            .highlight_style(app.theme.selection)
+            .highlight_symbol("> ")
+            .highlight_spacing(ratatui::widgets::HighlightSpacing::Always);
+
+        let len = list.len();
+
+        frame.render_stateful_widget(list, layout[0], &mut list_state);
+        self.set_list_area(inner, &list_state, len);
+
+        if let Some(line) = bottom_line {
+            frame.render_widget(Line::from(line), layout[1]);
+        }
+
+        Ok(())
+    }
+
+    fn equalizer(&self, app: &App, frame: &mut Frame, area: Rect) {
+        let equalizer = &app.equalizer;
+
+        // Characters on each side of the 0 dB line.
+        const HALF_WIDTH: usize = 24;
+
+        let layout = Layout::new(
+            Direction::Vertical,
+            vec![
+                Constraint::Fill(1),
+                Constraint::Max(if equalizer.get_status().is_some() {
+                    1
+                } else {
+                    0
+                }),
+            ],
+        )
+        .split(area);
+
+        let graphic_bands = GRAPHIC_FREQUENCIES.len();
+
+        let list_items: Vec<String> = equalizer
+            .get_bands()
+            .iter()
+            .enumerate()
+            .map(|(i, band)| {
+                let filled = ((band.gain.abs() / MAX_GAIN) * HALF_WIDTH as f32).round() as usize;
+
+                let (left, right) = if band.gain < 0.0 {
+                    (
+                        format!("{}{}", " ".repeat(HALF_WIDTH - filled), "█".repeat(filled)),
+                        " ".repeat(HALF_WIDTH),
+                    )
+                } else {
+                    (
+                        " ".repeat(HALF_WIDTH),
+                        format!("{}{}", "█".repeat(filled), " ".repeat(HALF_WIDTH - filled)),
+                    )
+                };
+
+                let frequency = if band.frequency >= 1000.0 {
+                    format!("{:.1} kHz", band.frequency / 1000.0)
+                } else {
+                    format!("{:.0} Hz", band.frequency)
+                };
+
+                let q = if i >= graphic_bands {
+                    format!(" Q {:.1}", band.q)
+                } else {
+                    String::new()
+                };
+
+                format!("{frequency:>9} {left}|{right} {:+5.1} dB{q}", band.gain)
+            })
+            .collect();
+
+        let title = format!(
+            " {} [{}{}] ",
+            equalizer.get_device(),
+            if equalizer.get_settings().enabled {
+                "on"
+            } else {
+                "off"
+            },
+            equalizer
+                .get_preset_name()
+                .map(|name| format!(", {name}"))
+                .unwrap_or_default()
+        );
+
+        let list = List::new(list_items)
+            .block(Block::new().title(title))
+            .highlight_style(app.theme.selection)
+            .highlight_symbol("> ")
+            .highlight_spacing(ratatui::widgets::HighlightSpacing::Always);
+
+        let mut list_state = ListState::default();
+        list_state.select(Some(equalizer.get_selected()));
+
+        frame.render_stateful_widget(list, layout[0], &mut list_state);
+
+        if let Some(status) = equalizer.get_status() {
+            frame.render_widget(Line::from(status), layout[1]);
+        }
+    }
+
+    fn devices(&self, app: &App, frame: &mut Frame, area: Rect) {
+        let devices = &app.devices;
+
+        let layout = Layout::new(
+            Direction::Vertical,
+            vec![
+                Constraint::Fill(1),
+                Constraint::Max(if devices.get_status().is_some() { 1 } else { 0 }),
+            ],
+        )
+        .split(area);
+
+        let title = match app.player_controller.get_device() {
+            Some(device) => format!(" Playing on {device} "),
+            None => String::from(" No output device "),
+        };
+
+        let block = Block::new().title(title);
+        let inner = block.inner(layout[0]);
+
+        let list = List::new(devices.get_entries())
+            .block(block)
+            .highlight_style(app.theme.selection)
+            .highlight_symbol("> ")
+            .highlight_spacing(ratatui::widgets::HighlightSpacing::Always);
+
+        let mut list_state = ListState::default();
+        list_state.select(Some(devices.get_selected()));
+
+        let len = list.len();
+
+        frame.render_stateful_widget(list, layout[0], &mut list_state);
+        self.set_list_area(inner, &list_state, len);
+
+        if let Some(status) = devices.get_status() {
+            frame.render_widget(Line::from(status), layout[1]);
+        }
+    }
+
+    fn visualizer(&self, app: &App, frame: &mut Frame, area: Rect) {
+        let visualizer = &app.visualizer;
+
+        match visualizer.get_mode() {
+            VisualizerMode::Spectrum => {
+                // Heights are drawn in thousandths of the full height.
+                let bars: Vec<Bar> = visualizer
+                    .get_bars()
+                    .iter()
+                    .map(|height| {
+                        Bar::default()
+                            .value((height * 1000.0) as u64)
+                            .text_value(String::new())
+                    })
+                    .collect();
+
+                let bar_width = (area.width / BARS as u16).saturating_sub(1).max(1);
+
+                let chart = BarChart::default()
+                    .block(Block::new().title(" Spectrum "))
+                    .data(BarGroup::default().bars(&bars))
+                    .bar_width(bar_width)
+                    .bar_gap(1)
+                    .max(1000)
+                    .bar_style(app.theme.accent);
+
+                frame.render_widget(chart, area);
+            }
+            VisualizerMode::Waveform => {
+                let points: Vec<(f64, f64)> = visualizer
+                    .get_waveform()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, sample)| (i as f64, *sample as f64))
+                    .collect();
+
+                let dataset = Dataset::default()
+                    .marker(Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(app.theme.accent)
+                    .data(&points);
+
+                let chart = Chart::new(vec![dataset])
+                    .block(Block::new().title(" Waveform "))
+                    .x_axis(Axis::default().bounds([0.0, points.len().max(1) as f64]))
+                    .y_axis(Axis::default().bounds([-1.0, 1.0]));
+
+                frame.render_widget(chart, area);
+            }
+        }
+    }
+
+    /// Everything known about the current song, and the next few songs.
+    fn now_playing(&self, app: &App, frame: &mut Frame, area: Rect) -> color_eyre::Result<()> {
+        let pc = &app.player_controller;
+        let queue = pc.queue.get_queue()?;
+
+        let (Some(song), Some(details)) = (queue.first(), app.now_playing.get_details()) else {
+            let message = match queue.first() {
+                Some(_) if app.now_playing.is_loading() => "Reading the file...",
+                _ => "Nothing is playing.",
+            };
+
+            frame.render_widget(Paragraph::new(message).centered(), area);
+            return Ok(());
+        };
+
+        let tags = &details.tags;
+        let gain = &details.replay_gain;
+
+        // Tracks of a CUE sheet have their own title and artist, the file's
+        // tags describe the whole album.
+        let (title, artist) = if song.get_start().is_some() {
+            (song.get_title()?, song.get_artist()?)
+        } else {
+            (
+                tags.title.clone().or(song.get_title()?),
+                tags.artist.clone().or(song.get_artist()?),
+            )
+        };
+
+        let track = match (tags.track_number, tags.disc_number) {
+            (Some(track), Some(disc)) => Some(format!("{track} (disc {disc})")),
+            (Some(track), None) => Some(track.to_string()),
+            (None, Some(disc)) => Some(format!("Disc {disc}")),
+            (None, None) => None,
+        };
+
+        let replay_gain = [
+            ("track", gain.track_gain, gain.track_peak),
+            ("album", gain.album_gain, gain.album_peak),
+        ]
+        .iter()
+        .filter_map(|(kind, gain, peak)| {
+            let gain = format!("{kind} {:+.2} dB", (*gain)?);
+
+            Some(match peak {
+                Some(peak) => format!("{gain}, peak {peak:.3}"),
+                None => gain,
+            })
+        })
+        .collect::<Vec<String>>()
+        .join(" / ");
+
+        let rows: Vec<(&str, Option<String>)> = vec![
+            ("Title", title),
+            ("Artist", artist),
+            ("Album", tags.album.clone()),
+            ("Track", track),
+            ("Year", tags.year.map(|year| year.to_string())),
+            ("Genre", tags.genre.clone()),
+            (
+                "Position",
+                Some(format!(
+                    "{} / {}",
+                    format_time(pc.get_position()),
+                    pc.get_length()
+                        .map(format_time)
+                        .unwrap_or_else(|| String::from("?"))
+                )),
+            ),
+            ("Format", details.format.map(String::from)),
+            (
+                "Bitrate",
+                details.bitrate.map(|rate| format!("{rate} kbps")),
+            ),
+            (
+                "Sample rate",
+                details
+                    .sample_rate
+                    .map(|rate| format!("{:.1} kHz", rate as f32 / 1000.0)),
+            ),
+            (
+                "Channels",
+                details.channels.map(|channels| match channels {
+                    1 => String::from("1 (mono)"),
+                    2 => String::from("2 (stereo)"),
+                    channels => channels.to_string(),
+                }),
+            ),
+            ("Path", Some(song.get_path().display().to_string())),
+            (
+                "ReplayGain",
+                Some(replay_gain).filter(|gain| !gain.is_empty()),
+            ),
+            (
+                "Applied gain",
+                pc.get_replay_gain().map(|gain| format!("{gain:+.2} dB")),
+            ),
+            (
+                "Play count",
+                Some(app.library.get_play_count(&song.get_path()).to_string()),
+            ),
+        ];
+
+        let lines: Vec<Line> = rows
+            .into_iter()
+            .filter_map(|(label, value)| {
+                Some(Line::from(vec![
+                    Span::styled(format!("{label:>13}  "), app.theme.muted),
+                    Span::raw(value?),
+                ]))
+            })
+            .collect();
+
+        let up_next: Vec<Line> = match queue.len() {
+            0 | 1 => vec![Line::from("Nothing queued.").style(app.theme.muted)],
+            len => queue[1..]
+                .iter()
+                .take(UP_NEXT)
+                .enumerate()
+                .map(|(i, song)| {
+                    let title = song.get_title().ok().flatten().unwrap_or_default();
+
+                    Line::from(match song.get_artist().ok().flatten() {
+                        Some(artist) => format!("{}. {artist} - {title}", i + 1),
+                        None => format!("{}. {title}", i + 1),
+                    })
+                })
+                .chain((len - 1 > UP_NEXT).then(|| {
+                    Line::from(format!("and {} more", len - 1 - UP_NEXT)).style(app.theme.muted)
+                }))
+                .collect(),
+        };
+
+        let [details_area, up_next_area] = Layout::vertical([
+            Constraint::Length(lines.len() as u16 + 1),
+            Constraint::Fill(1),
+        ])
+        .areas(area);
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::new().title(" Now playing ")),
+            details_area,
+        );
+        frame.render_widget(
+            Paragraph::new(up_next).block(Block::new().borders(Borders::TOP).title(" Up next ")),
+            up_next_area,
+        );
+
+        Ok(())
+    }
+
+    /// Every notification since the start, newest last.
+    fn log(&self, app: &App, frame: &mut Frame, area: Rect) {
+        let log = app.notifications.get_log();
+
+        if log.is_empty() {
+            frame.render_widget(Paragraph::new("No messages.").centered(), area);
+            return;
+        }
+
+        let list_items: Vec<ListItem> = log
+            .iter()
+            .map(|notification| {
+                let line = Line::from(vec![
+                    Span::styled(
+                        format!("{:>9}  ", format_age(notification.time.elapsed())),
+                        app.theme.muted,
+                    ),
+                    Span::raw(format_notification(notification)),
+                ]);
+
+                match notification.level {
+                    Level::Info => ListItem::new(line),
+                    Level::Error => ListItem::new(line).style(app.theme.error),
+                }
+            })
+            .collect();
+
+        let list = List::new(list_items)
+            .highlight_style(app.theme.selection)
+            .highlight_symbol("> ")
+            .highlight_spacing(ratatui::widgets::HighlightSpacing::Always);
+
+        let mut list_state = ListState::default();
+        list_state.select(Some(app.notifications.get_selected()));
+
+        let len = list.len();
+
+        frame.render_stateful_widget(list, area, &mut list_state);
+        self.set_list_area(area, &list_state, len);
+    }
+}
+
+/// The message, with how often it was reported if more than once.
+fn format_notification(notification: &Notification) -> String {
+    match notification.count {
+        1 => notification.message.clone(),
+        count => format!("{} ({count}x)", notification.message),
+    }
+}
+
+/// Rough time since `time`, like "5m ago".
+fn format_age(time: Duration) -> String {
+    match time.as_secs() {
+        0..60 => String::from("just now"),
+        seconds @ 60..3600 => format!("{}m ago", seconds / 60),
+        seconds => format!("{}h ago", seconds / 3600),
+    }
+}
+
+/// `m:ss`, or `h:mm:ss` for an hour or more.
+fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{hours}:{:02}:{:02}", seconds / 60 % 60, seconds % 60),
+    }
 }
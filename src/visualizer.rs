@@ -0,0 +1,188 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::{
+    collections::VecDeque,
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Samples analysed per frame, about 46 ms at 44.1 kHz.
+const FFT_SIZE: usize = 2048;
+
+/// Time between two frames of the visualizer.
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Bars of the spectrum, spaced logarithmically.
+pub const BARS: usize = 48;
+
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 16_000.0;
+
+/// Levels below this are drawn as empty bars.
+const FLOOR_DB: f32 = -70.0;
+
+/// How far a bar may fall per frame, rising is immediate.
+const FALL_PER_FRAME: f32 = 0.04;
+
+/// The most recent samples sent to the output, downmixed to mono.
+#[derive(Clone)]
+pub struct Tap {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: Arc<AtomicU32>,
+}
+
+impl Tap {
+    pub fn new() -> Tap {
+        Tap {
+            samples: Arc::new(Mutex::new(VecDeque::with_capacity(FFT_SIZE))),
+            sample_rate: Arc::new(AtomicU32::new(44_100)),
+        }
+    }
+
+    /// Called from the audio thread. Skips the chunk rather than waiting
+    /// when the UI is reading, so the tap can't cause a dropout.
+    pub fn push(&self, samples: &[f32], channels: u16, sample_rate: u32) {
+        let channels = channels.max(1) as usize;
+
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+
+        if let Ok(mut buffer) = self.samples.try_lock() {
+            for frame in samples.chunks_exact(channels) {
+                if buffer.len() == FFT_SIZE {
+                    buffer.pop_front();
+                }
+
+                buffer.push_back(frame.iter().sum::<f32>() / channels as f32);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Vec<f32> {
+        self.samples
+            .lock()
+            .map(|buffer| buffer.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VisualizerMode {
+    Spectrum,
+    Waveform,
+}
+
+/// State of the visualizer window. The analysis runs at most once per
+/// [`FRAME_INTERVAL`] no matter how often the UI is drawn.
+pub struct Visualizer {
+    tap: Tap,
+    mode: VisualizerMode,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    waveform: Vec<f32>,
+    /// Bar heights between 0 and 1.
+    bars: Vec<f32>,
+    last_frame: Option<Instant>,
+}
+
+impl Visualizer {
+    pub fn new() -> Visualizer {
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FFT_SIZE as f32).cos())
+            .collect();
+
+        Visualizer {
+            tap: Tap::new(),
+            mode: VisualizerMode::Spectrum,
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            waveform: vec![],
+            bars: vec![0.0; BARS],
+            last_frame: None,
+        }
+    }
+
+    pub fn get_tap(&self) -> Tap {
+        self.tap.clone()
+    }
+
+    pub fn get_mode(&self) -> VisualizerMode {
+        self.mode
+    }
+
+    pub fn get_waveform(&self) -> &[f32] {
+        &self.waveform
+    }
+
+    pub fn get_bars(&self) -> &[f32] {
+        &self.bars
+    }
+
+    pub fn handle_key_event(&mut self, key_event: KeyEvent) {
+        if let KeyCode::Char('m') = key_event.code {
+            self.mode = match self.mode {
+                VisualizerMode::Spectrum => VisualizerMode::Waveform,
+                VisualizerMode::Waveform => VisualizerMode::Spectrum,
+            };
+        }
+    }
+
+    /// Analyses the latest samples if a frame is due.
+    pub fn update(&mut self) {
+        if self
+            .last_frame
+            .is_some_and(|last| last.elapsed() < FRAME_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_frame = Some(Instant::now());
+
+        let mut samples = self.tap.snapshot();
+        samples.resize(FFT_SIZE, 0.0);
+
+        let mut buffer: Vec<Complex<f32>> = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, window)| Complex::new(sample * window, 0.0))
+            .collect();
+
+        self.fft.process(&mut buffer);
+
+        // A full scale sine reaches 0 dB, the Hann window halves the sum.
+        let scale = 4.0 / FFT_SIZE as f32;
+
+        let spectrum: Vec<f32> = buffer[..FFT_SIZE / 2]
+            .iter()
+            .map(|bin| 20.0 * (bin.norm() * scale).max(1e-9).log10())
+            .collect();
+
+        let bin_width = self.tap.sample_rate() as f32 / FFT_SIZE as f32;
+        let ratio = MAX_FREQUENCY / MIN_FREQUENCY;
+
+        for (i, bar) in self.bars.iter_mut().enumerate() {
+            let low = MIN_FREQUENCY * ratio.powf(i as f32 / BARS as f32);
+            let high = MIN_FREQUENCY * ratio.powf((i + 1) as f32 / BARS as f32);
+
+            let first = ((low / bin_width) as usize).min(spectrum.len() - 1);
+            let last = ((high / bin_width) as usize).clamp(first + 1, spectrum.len());
+
+            let level = spectrum[first..last]
+                .iter()
+                .copied()
+                .fold(FLOOR_DB, f32::max);
+            let height = (1.0 - level / FLOOR_DB).clamp(0.0, 1.0);
+
+            *bar = height.max(*bar - FALL_PER_FRAME);
+        }
+
+        self.waveform = samples;
+    }
+}